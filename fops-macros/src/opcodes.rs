@@ -9,16 +9,31 @@ pub fn opcodes(item: TokenStream) -> TokenStream {
 
     let module_ident = input.module_ident;
     let entries = input.entries.iter();
-    let count = entries.len();
-    let instruction_names = input.entries.iter().map(|e| e.ident.to_string()).collect::<Vec<String>>();
-    let instruction_sizes = input.entries.iter().map(|e| e.length as u8).collect::<Vec<u8>>();
+
+    // Both tables are indexed by opcode, leaving gaps for the bytes which aren't opcodes
+    let mut instruction_names = vec![String::new(); 256];
+    let mut instruction_sizes = vec![0u8; 256];
+    for entry in input.entries.iter() {
+        let code = match entry.code.base10_parse::<u8>() {
+            Ok(code) => code as usize,
+            Err(error) => return error.to_compile_error().into(),
+        };
+        if instruction_sizes[code] != 0 {
+            return syn::Error::new(entry.code.span(), "Duplicate opcode").to_compile_error().into();
+        }
+        instruction_names[code] = entry.ident.to_string();
+        instruction_sizes[code] = entry.length as u8;
+    }
 
     quote!(
         pub mod #module_ident {
             #(#entries)*
 
-            pub const INSTRUCTION_NAMES: [&str; #count] = [#(#instruction_names,)*];
-            pub const INSTRUCTION_LENGTH: [u8; #count] = [#(#instruction_sizes,)*];
+            /// Names of the instructions indexed by opcode, empty for bytes which aren't opcodes
+            pub const INSTRUCTION_NAMES: [&str; 256] = [#(#instruction_names,)*];
+            /// Lengths of the instructions including their operands, indexed by opcode.
+            /// Bytes which aren't opcodes have a length of 0.
+            pub const INSTRUCTION_LENGTH: [u8; 256] = [#(#instruction_sizes,)*];
         }
     ).into()
 }
@@ -47,6 +47,7 @@ enum Input {
     Float(syn::LitFloat),
     FloatIdentifier(syn::Ident),
     String(syn::LitStr),
+    Byte(syn::LitInt),
 }
 
 enum Expected {
@@ -73,6 +74,8 @@ impl Parse for VmTest {
                 inputs.push(Input::Float(input.parse()?));
             } else if input.peek(syn::LitStr) {
                 inputs.push(Input::String(input.parse()?));
+            } else if input.peek(syn::LitInt) {
+                inputs.push(Input::Byte(input.parse()?));
             }
 
             if input.peek(Token![,]) {
@@ -112,7 +115,10 @@ impl Input {
                 chunk.write_f64_0(#ident);
             },
             Input::String(lit) => quote! {
-                chunk.write_constant(crate::vm::value::Value::from(#lit), 0).unwrap();
+                chunk.write_constant(crate::vm::value::Value::from(#lit), crate::bytecode::chunk::Position::default()).unwrap();
+            },
+            Input::Byte(lit) => quote! {
+                chunk.write0(#lit);
             }
         }
     }
@@ -1,13 +1,15 @@
+pub mod assembler;
 pub mod disassembler;
 pub mod chunk;
+pub mod verifier;
 
 fops_macros::opcodes! {
     codes:
-    0x00 = OP_F64 len 5,
+    0x00 = OP_F64 len 9,
     0x01 = OP_NIL,
     0x02 = OP_TRUE,
     0x03 = OP_FALSE,
-    0x04 = OP_CONTANT,
+    0x04 = OP_CONTANT len 2,
     
     0x05 = OP_ADD,
     0x06 = OP_SUBTRACT,
@@ -24,5 +26,30 @@ fops_macros::opcodes! {
     0x16 = OP_GREATER_THAN_OR_EQUALS,
     
     0x17 = OP_POP,
-    0x18 = OP_RETURN
+    0x18 = OP_RETURN,
+
+    0x19 = OP_GET_LOCAL len 2,
+    0x1A = OP_SET_LOCAL len 2,
+    0x1B = OP_GET_GLOBAL len 2,
+    0x1C = OP_SET_GLOBAL len 2,
+    0x1D = OP_DEFINE_GLOBAL len 2,
+
+    0x1E = OP_JUMP len 3,
+    0x1F = OP_JUMP_IF_FALSE len 3,
+    0x20 = OP_LOOP len 3,
+
+    0x21 = OP_CALL len 2,
+    // Followed by a pair of (is_local, index) bytes for each upvalue of the function
+    0x22 = OP_CLOSURE len 2,
+    0x23 = OP_GET_UPVALUE len 2,
+    0x24 = OP_SET_UPVALUE len 2,
+    0x25 = OP_CLOSE_UPVALUE,
+
+    // Like OP_CONTANT, but with a 24 bit big endian constant index
+    0x26 = OP_CONSTANT_LONG len 4,
+    // Like the instructions without the suffix, for name and function constants past index 255
+    0x27 = OP_GET_GLOBAL_LONG len 4,
+    0x28 = OP_SET_GLOBAL_LONG len 4,
+    0x29 = OP_DEFINE_GLOBAL_LONG len 4,
+    0x2A = OP_CLOSURE_LONG len 4
 }
\ No newline at end of file
@@ -0,0 +1,564 @@
+use crate::bytecode::chunk::{Chunk, Position, MAX_CONSTANT_INDEX};
+use crate::bytecode::codes::*;
+use crate::vm::value::{Function, Obj, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Parses the text format of the disassembler back into a chunk.
+///
+/// Besides reading disassembler output, the format is meant to be written by hand:
+///
+/// ```text
+/// ; Comments run to the end of the line
+/// .const limit 3          ; Names a constant for use as an operand
+/// .line 2:5               ; Sets the position of the instructions that follow, the column is optional
+///     OP_F64 0
+/// loop:                   ; Labels can be used as jump targets
+///     OP_GET_LOCAL 0
+///     OP_CONTANT limit
+///     OP_LESS_THAN
+///     OP_JUMP_IF_FALSE -> end
+///     OP_POP
+///     OP_GET_LOCAL 0
+///     OP_F64 1
+///     OP_ADD
+///     OP_SET_LOCAL 0
+///     OP_POP
+///     OP_LOOP -> loop
+/// end:
+///     OP_POP
+///     OP_CLOSURE <fun f>
+///     local 0             ; Upvalues captured by the closure
+///     OP_RETURN
+///
+/// == f (arity 0) ==
+///     OP_GET_UPVALUE 0
+///     OP_RETURN
+/// ```
+///
+/// The offset and position columns of disassembled instructions are optional.
+/// Constant operands may be preceded by their index in the constant pool.
+/// A function constant takes its body from the next section not used by another function,
+/// in the order the disassembler prints them.
+pub fn assemble(source: &str) -> Result<Chunk, String> {
+    let sections = split_sections(source)?;
+    let mut next_section = 1;
+    let (chunk, _) = assemble_section(&sections, 0, &mut next_section)?;
+
+    if let Some(section) = sections.get(next_section) {
+        return Err(error(section.header_line, format!("Function {} is not used by any closure", section.name)));
+    }
+    Ok(chunk)
+}
+
+struct Section<'a> {
+    name: String,
+    arity: u8,
+    header_line: usize,
+    lines: Vec<(usize, &'a str)>,
+}
+
+/// A constant pool entry, where functions are only known by name until their section is assembled
+enum Constant {
+    Value(Value),
+    Function { name: String, upvalue_count: Option<u8> },
+}
+
+/// An instruction operand referring to a jump target which may not have been seen yet
+struct PendingJump {
+    text_line: usize,
+    opcode: u8,
+    offset: usize,
+    target: String,
+}
+
+fn error(text_line: usize, message: impl AsRef<str>) -> String {
+    format!("[Line {}] {}", text_line, message.as_ref())
+}
+
+fn split_sections(source: &str) -> Result<Vec<Section<'_>>, String> {
+    let mut sections = vec![Section { name: "script".to_string(), arity: 0, header_line: 0, lines: Vec::new() }];
+
+    for (index, line) in source.lines().enumerate() {
+        let text_line = index + 1;
+        let trimmed = line.trim();
+
+        if let Some(header) = trimmed.strip_prefix("==").and_then(|rest| rest.strip_suffix("==")) {
+            let (name, arity) = parse_header(header.trim()).ok_or_else(|| error(text_line, "Malformed section header"))?;
+            // Sources starting with a header don't have an implicit script section
+            if sections.len() == 1 && sections[0].lines.is_empty() && sections[0].header_line == 0 {
+                sections.clear();
+            }
+            sections.push(Section { name, arity, header_line: text_line, lines: Vec::new() });
+        } else if !trimmed.is_empty() && !trimmed.starts_with(';') {
+            sections.last_mut().unwrap().lines.push((text_line, line));
+        }
+    }
+    Ok(sections)
+}
+
+/// Parses `name` or `name (arity N)`
+fn parse_header(header: &str) -> Option<(String, u8)> {
+    match header.split_once(" (arity ") {
+        None if !header.is_empty() && !header.contains(char::is_whitespace) => Some((header.to_string(), 0)),
+        None => None,
+        Some((name, rest)) => {
+            let arity = rest.strip_suffix(')')?.trim().parse().ok()?;
+            Some((name.to_string(), arity))
+        }
+    }
+}
+
+/// Assembles a section along with the sections of the functions in its constants,
+/// returning the chunk and the name of the section
+fn assemble_section(sections: &[Section], index: usize, next_section: &mut usize) -> Result<(Chunk, String), String> {
+    let section = &sections[index];
+    let mut chunk = Chunk::new();
+    let mut pool: Vec<Option<Constant>> = Vec::new();
+    let mut named_constants: HashMap<String, usize> = HashMap::new();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut jumps: Vec<PendingJump> = Vec::new();
+    let mut position = Position::default();
+    // The function constant of the closure whose upvalues are being listed, and how many were listed so far
+    let mut closure: Option<(usize, u8)> = None;
+
+    for &(text_line, text) in &section.lines {
+        let mut tokens = tokenize(text).map_err(|message| error(text_line, message))?;
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if tokens.len() == 1 && tokens[0].ends_with(':') {
+            let label = tokens[0].trim_end_matches(':').to_string();
+            if labels.insert(label.clone(), chunk.code.len()).is_some() {
+                return Err(error(text_line, format!("Label {} is defined twice", label)));
+            }
+            continue;
+        }
+
+        match tokens[0].as_str() {
+            ".line" => {
+                position = match tokens.as_slice() {
+                    [_, token] => parse_position(token).ok_or_else(|| error(text_line, format!("Invalid position {}", token)))?,
+                    _ => return Err(error(text_line, "Expected .line <line>[:<column>]")),
+                };
+                continue;
+            }
+            ".const" => {
+                let (name, value) = match tokens.as_slice() {
+                    [_, name, value] if is_identifier(name) => (name.clone(), value.as_str()),
+                    _ => return Err(error(text_line, "Expected .const <name> <value>")),
+                };
+                let constant = parse_constant(value).map_err(|message| error(text_line, message))?;
+                let constant_index = add_constant(&mut pool, None, constant).map_err(|message| error(text_line, message))?;
+                named_constants.insert(name, constant_index);
+                continue;
+            }
+            _ => {}
+        }
+
+        // Offset and position columns as printed by the disassembler
+        if tokens[0].chars().all(|c| c.is_ascii_digit()) {
+            tokens.remove(0);
+            match tokens.first().map(String::as_str) {
+                Some("|") => {}
+                // The line is the same as the previous instruction's, but the column is not
+                Some(token) if token.starts_with("|:") => {
+                    let column = token[2..].parse().map_err(|_| error(text_line, format!("Invalid position {}", token)))?;
+                    position = Position::new(position.line, column);
+                }
+                Some(token) => {
+                    position = parse_position(token).ok_or_else(|| error(text_line, format!("Invalid position {}", token)))?;
+                }
+                None => return Err(error(text_line, "Expected a position or '|' after the offset")),
+            }
+            tokens.remove(0);
+        }
+
+        let (mnemonic, operands) = tokens.split_first().ok_or_else(|| error(text_line, "Expected an instruction"))?;
+
+        if mnemonic == "local" || mnemonic == "upvalue" {
+            let (_, count) = closure.as_mut().ok_or_else(|| error(text_line, "Upvalues must follow OP_CLOSURE"))?;
+            let index = match operands {
+                [index] => parse_byte(index).map_err(|message| error(text_line, message))?,
+                _ => return Err(error(text_line, format!("Expected {} <index>", mnemonic))),
+            };
+            chunk.write((mnemonic == "local") as u8, position);
+            chunk.write(index, position);
+            *count = count.checked_add(1).ok_or_else(|| error(text_line, "Too many upvalues"))?;
+            continue;
+        }
+        finish_closure(&mut pool, closure.take()).map_err(|message| error(text_line, message))?;
+
+        let opcode = INSTRUCTION_NAMES.iter()
+            .position(|name| !name.is_empty() && name == mnemonic)
+            .ok_or_else(|| error(text_line, format!("Unknown instruction {}", mnemonic)))? as u8;
+        let offset = chunk.code.len();
+        chunk.write(opcode, position);
+
+        match opcode {
+            OP_F64 => {
+                let value = match operands {
+                    [value] => value.parse::<f64>().map_err(|_| error(text_line, format!("Invalid number {}", value)))?,
+                    _ => return Err(error(text_line, "Expected a number")),
+                };
+                f64::to_be_bytes(value).iter().for_each(|byte| chunk.write(*byte, position));
+            }
+            OP_CONTANT | OP_CONSTANT_LONG | OP_GET_GLOBAL | OP_GET_GLOBAL_LONG | OP_SET_GLOBAL | OP_SET_GLOBAL_LONG
+            | OP_DEFINE_GLOBAL | OP_DEFINE_GLOBAL_LONG | OP_CLOSURE | OP_CLOSURE_LONG => {
+                let constant_index = match operands {
+                    [name] if named_constants.contains_key(name) => named_constants[name],
+                    [value] => {
+                        let constant = parse_constant(value).map_err(|message| error(text_line, message))?;
+                        add_constant(&mut pool, None, constant).map_err(|message| error(text_line, message))?
+                    }
+                    [index, value] => {
+                        let index = index.parse::<usize>()
+                            .map_err(|_| error(text_line, format!("Invalid constant index {}", index)))?;
+                        let constant = parse_constant(value).map_err(|message| error(text_line, message))?;
+                        add_constant(&mut pool, Some(index), constant).map_err(|message| error(text_line, message))?
+                    }
+                    _ => return Err(error(text_line, "Expected a constant")),
+                };
+                // The long variants have a 24 bit operand
+                if INSTRUCTION_LENGTH[opcode as usize] == 4 {
+                    let [_, high, middle, low] = (constant_index as u32).to_be_bytes();
+                    [high, middle, low].iter().for_each(|byte| chunk.write(*byte, position));
+                } else {
+                    let index = u8::try_from(constant_index)
+                        .map_err(|_| error(text_line, format!("Constant index {} doesn't fit in {}", constant_index, mnemonic)))?;
+                    chunk.write(index, position);
+                }
+
+                if opcode == OP_CLOSURE || opcode == OP_CLOSURE_LONG {
+                    match &pool[constant_index] {
+                        Some(Constant::Function { .. }) => closure = Some((constant_index, 0)),
+                        _ => return Err(error(text_line, format!("{} expects a function constant", mnemonic))),
+                    }
+                }
+            }
+            OP_GET_LOCAL | OP_SET_LOCAL | OP_GET_UPVALUE | OP_SET_UPVALUE | OP_CALL => {
+                let operand = match operands {
+                    [operand] => parse_byte(operand).map_err(|message| error(text_line, message))?,
+                    _ => return Err(error(text_line, "Expected a byte operand")),
+                };
+                chunk.write(operand, position);
+            }
+            OP_JUMP | OP_JUMP_IF_FALSE | OP_LOOP => {
+                let target = match operands {
+                    [_, arrow, target] | [arrow, target] if arrow == "->" => target.clone(),
+                    [target] => target.clone(),
+                    _ => return Err(error(text_line, "Expected a jump target")),
+                };
+                chunk.write(0xff, position);
+                chunk.write(0xff, position);
+                jumps.push(PendingJump { text_line, opcode, offset, target });
+            }
+            _ if !operands.is_empty() => {
+                return Err(error(text_line, format!("{} takes no operands", mnemonic)));
+            }
+            _ => {}
+        }
+    }
+    finish_closure(&mut pool, closure.take()).map_err(|message| error(section.header_line, message))?;
+
+    for jump in jumps {
+        let target = match labels.get(&jump.target) {
+            Some(target) => *target,
+            None => jump.target.parse::<usize>()
+                .map_err(|_| error(jump.text_line, format!("Unknown label {}", jump.target)))?,
+        };
+        let after = jump.offset + 3;
+        let distance = if jump.opcode == OP_LOOP { after.checked_sub(target) } else { target.checked_sub(after) };
+        let distance = distance
+            .and_then(|distance| u16::try_from(distance).ok())
+            .ok_or_else(|| error(jump.text_line, format!("Can't jump from {} to {}", jump.offset, target)))?;
+        chunk.code[jump.offset + 1..jump.offset + 3].copy_from_slice(&distance.to_be_bytes());
+    }
+
+    for (constant_index, constant) in pool.into_iter().enumerate() {
+        let value = match constant {
+            None => return Err(error(section.header_line, format!("Constant {} of {} is never defined", constant_index, section.name))),
+            Some(Constant::Value(value)) => value,
+            Some(Constant::Function { name, upvalue_count }) => {
+                let function_section = *next_section;
+                if function_section >= sections.len() {
+                    return Err(error(section.header_line, format!("Missing section for function {}", name)));
+                }
+                *next_section += 1;
+
+                let (function_chunk, section_name) = assemble_section(sections, function_section, next_section)?;
+                if section_name != name {
+                    let header_line = sections[function_section].header_line;
+                    return Err(error(header_line, format!("Expected section for function {}, got {}", name, section_name)));
+                }
+                let function = Function {
+                    name,
+                    arity: sections[function_section].arity,
+                    upvalue_count: upvalue_count.unwrap_or(0),
+                    chunk: function_chunk,
+                };
+                Value::Obj(Obj::Function(Rc::new(function)))
+            }
+        };
+        chunk.push_constant(value)?;
+    }
+
+    Ok((chunk, section.name.clone()))
+}
+
+/// Records how many upvalues the closure listed, which every closure of the function must agree on
+fn finish_closure(pool: &mut [Option<Constant>], closure: Option<(usize, u8)>) -> Result<(), String> {
+    let Some((function, count)) = closure else { return Ok(()) };
+    match pool.get_mut(function) {
+        Some(Some(Constant::Function { name, upvalue_count })) => match upvalue_count {
+            Some(existing) if *existing != count => {
+                Err(format!("Closures of {} capture {} and {} upvalues", name, existing, count))
+            }
+            _ => {
+                *upvalue_count = Some(count);
+                Ok(())
+            }
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Puts the constant at `index`, or at the end of the pool if no index is given
+fn add_constant(pool: &mut Vec<Option<Constant>>, index: Option<usize>, constant: Constant) -> Result<usize, String> {
+    let index = index.unwrap_or(pool.len());
+    if index > MAX_CONSTANT_INDEX {
+        return Err(format!("More than {} constants", MAX_CONSTANT_INDEX + 1));
+    }
+    if index >= pool.len() {
+        pool.resize_with(index + 1, || None);
+    }
+
+    match (&pool[index], &constant) {
+        (None, _) => pool[index] = Some(constant),
+        (Some(Constant::Value(existing)), Constant::Value(value)) if existing == value => {}
+        (Some(Constant::Function { name: existing, .. }), Constant::Function { name, .. }) if existing == name => {}
+        _ => return Err(format!("Constant {} is defined twice with different values", index)),
+    }
+    Ok(index)
+}
+
+fn parse_constant(token: &str) -> Result<Constant, String> {
+    if let Some(name) = token.strip_prefix("<fun ").and_then(|rest| rest.strip_suffix('>')) {
+        return Ok(Constant::Function { name: name.to_string(), upvalue_count: None });
+    }
+    if let Some(string) = token.strip_prefix('"') {
+        return Ok(Constant::Value(Value::from(string.to_string())));
+    }
+
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "nil" => Value::Nil,
+        _ => Value::Number(token.parse::<f64>().map_err(|_| format!("Invalid constant {}", token))?),
+    };
+    Ok(Constant::Value(value))
+}
+
+/// Parses `line` or `line:column`
+fn parse_position(token: &str) -> Option<Position> {
+    match token.split_once(':') {
+        Some((line, column)) => Some(Position::new(line.parse().ok()?, column.parse().ok()?)),
+        None => Some(Position::new(token.parse().ok()?, 0)),
+    }
+}
+
+fn parse_byte(token: &str) -> Result<u8, String> {
+    token.parse::<u8>().map_err(|_| format!("Expected a number from 0 to 255, got {}", token))
+}
+
+fn is_identifier(token: &str) -> bool {
+    let mut chars = token.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && !matches!(token, "true" | "false" | "nil")
+}
+
+/// Splits a line into tokens, keeping `<fun name>` whole and removing comments.
+/// String tokens keep their opening quote to tell them apart, but are otherwise unescaped.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::from('"');
+                loop {
+                    match chars.next() {
+                        None => return Err("Unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => string.push(unescape(&mut chars)?),
+                        Some(c) => string.push(c),
+                    }
+                }
+                tokens.push(string);
+            }
+            '<' => {
+                let mut token = String::new();
+                for c in chars.by_ref() {
+                    token.push(c);
+                    if c == '>' {
+                        break;
+                    }
+                }
+                if !token.ends_with('>') {
+                    return Err(format!("Unterminated {}", token));
+                }
+                tokens.push(token);
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads an escape sequence as produced by Rust's debug formatting of strings
+fn unescape(chars: &mut impl Iterator<Item = char>) -> Result<char, String> {
+    match chars.next() {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('r') => Ok('\r'),
+        Some('0') => Ok('\0'),
+        Some(c @ ('\\' | '"' | '\'')) => Ok(c),
+        Some('u') => {
+            if chars.next() != Some('{') {
+                return Err("Expected '{' after \\u".to_string());
+            }
+            let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+            u32::from_str_radix(&hex, 16).ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("Invalid unicode escape \\u{{{}}}", hex))
+        }
+        Some(c) => Err(format!("Unknown escape \\{}", c)),
+        None => Err("Unterminated string".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::assembler::assemble;
+    use crate::bytecode::chunk::{Chunk, Position};
+    use crate::bytecode::disassembler::disassemble;
+    use crate::vm::value::Value;
+
+    fn assert_round_trip(chunk: &Chunk) {
+        let text = disassemble(chunk, "script");
+        let assembled = assemble(&text).unwrap_or_else(|error| panic!("{}\n{}", error, text));
+        assert_eq!(assembled.code, chunk.code);
+        assert_eq!(disassemble(&assembled, "script"), text);
+    }
+
+    #[test]
+    fn round_trip_compiled_code() {
+        let sources = [
+            "let greeting = \"Hello\n\tfops\\n\\t\\\"quoted\\\" \\\\ \\u{1F600}\"; println(greeting + \"!\");",
+            "let a = 1; { let b = a; a = b * 2 + -3; } a == 1 || a != 2 && !true;",
+            "repeat (3) { if (true) break; else continue; } while (false) {} repeat { break; }",
+            "fun outer(x, y) {\n  fun inner() { return x + y; }\n  fun other() { fun deepest() { return inner; } }\n  return inner;\n}\nouter(1, 2)();",
+        ];
+        for source in sources {
+            let chunk = crate::compiler::compile(source, false).unwrap();
+            assert_round_trip(&chunk);
+        }
+
+        let long_constants: String = (0..300).map(|i| format!("\"{}\";", i)).collect();
+        assert_round_trip(&crate::compiler::compile(&long_constants, false).unwrap());
+        let long_operands = long_constants + "let x = 1; x = x + 1; { let a = x; fun f() { return a; } }";
+        assert_round_trip(&crate::compiler::compile(&long_operands, false).unwrap());
+    }
+
+    #[test]
+    fn labels_constants_and_lines() {
+        let source = "
+            ; Counts up to the limit
+            .const limit 3
+            .line 2
+                OP_F64 0
+            loop:
+                OP_GET_LOCAL 0
+                OP_CONTANT limit
+                OP_LESS_THAN
+                OP_JUMP_IF_FALSE -> end
+                OP_POP
+            .line 3:9
+                OP_GET_LOCAL 0
+                OP_F64 1
+                OP_ADD
+                OP_SET_LOCAL 0
+                OP_POP
+                OP_LOOP -> loop
+            end:
+                OP_POP
+                OP_RETURN
+        ";
+        let chunk = assemble(source).unwrap();
+        assert_eq!(chunk.position_at(0), Position::new(2, 0));
+        assert_eq!(chunk.position_at(chunk.code.len() - 1), Position::new(3, 9));
+        assert_eq!(crate::vm::run(&chunk), Ok(Value::Number(3.0)));
+        assert_round_trip(&chunk);
+    }
+
+    #[test]
+    fn closures() {
+        let source = "
+            OP_F64 5
+            OP_CLOSURE <fun get>
+            local 0
+            OP_CALL 0
+            OP_RETURN
+
+            == get ==
+            OP_GET_UPVALUE 0
+            OP_RETURN
+        ";
+        let chunk = assemble(source).unwrap();
+        assert_eq!(crate::vm::run(&chunk), Ok(Value::Number(5.0)));
+        assert_round_trip(&chunk);
+    }
+
+    #[test]
+    fn invalid_assembly() {
+        let cases = [
+            ("OP_FOO", "[Line 1] Unknown instruction OP_FOO"),
+            ("OP_NIL 1", "[Line 1] OP_NIL takes no operands"),
+            ("OP_F64", "[Line 1] Expected a number"),
+            ("OP_GET_LOCAL 256", "[Line 1] Expected a number from 0 to 255, got 256"),
+            ("\nOP_JUMP -> nowhere", "[Line 2] Unknown label nowhere"),
+            ("OP_LOOP -> 5", "[Line 1] Can't jump from 0 to 5"),
+            ("a:\na:", "[Line 2] Label a is defined twice"),
+            ("local 1", "[Line 1] Upvalues must follow OP_CLOSURE"),
+            ("OP_CONTANT \"unterminated", "[Line 1] Unterminated string"),
+            ("OP_CONTANT 1 \"a\"", "[Line 0] Constant 0 of script is never defined"),
+            ("OP_CONTANT 0 \"a\"\nOP_CONTANT 0 \"b\"", "[Line 2] Constant 0 is defined twice with different values"),
+            ("OP_CONSTANT_LONG 256 \"a\"\nOP_CONTANT 256 \"a\"", "[Line 2] Constant index 256 doesn't fit in OP_CONTANT"),
+            ("OP_CLOSURE <fun f>", "[Line 0] Missing section for function f"),
+            ("OP_CLOSURE <fun f>\n== g ==", "[Line 2] Expected section for function f, got g"),
+            ("OP_NIL\n== f ==", "[Line 2] Function f is not used by any closure"),
+            ("== f (arity x) ==", "[Line 1] Malformed section header"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(assemble(source).err().as_deref(), Some(expected), "{}", source);
+        }
+    }
+}
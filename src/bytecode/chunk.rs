@@ -1,10 +1,63 @@
-use crate::bytecode::codes::{OP_CONTANT, OP_F64};
-use crate::vm::value::Value;
+use crate::bytecode::codes::*;
+use crate::vm::value::{Obj, Value};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 
+mod serialization;
+
+/// Largest constant index that fits in the operand of OP_CONSTANT_LONG
+pub const MAX_CONSTANT_INDEX: usize = 0xFF_FFFF;
+/// How deeply functions may be nested in each other's constants, so that loading bytecode can't overflow the stack
+pub const MAX_FUNCTION_DEPTH: usize = 256;
+
+#[derive(Debug, Clone)]
 pub struct Chunk {
     pub(crate) code: Vec<u8>,
     constants: Vec<Value>,
-    lines: Vec<u16>
+    /// Where each number and string constant is in the pool, so that adding one again can reuse it
+    constant_indices: HashMap<ConstantKey, usize>,
+    /// Runs of code sharing a source position, ordered by the offset they start at
+    lines: Vec<LineRun>
+}
+
+/// A location in the source code, where 0 means unknown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub line: u32,
+    /// Counted in characters from the start of the line, starting at 1
+    pub column: u32,
+}
+
+/// Identifies a number by its bits, so that `0` and `-0` stay apart
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(String),
+}
+
+/// The code from `start` up to the start of the next run was compiled from `position`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineRun {
+    start: usize,
+    position: Position,
+}
+
+impl Position {
+    pub fn new(line: u32, column: u32) -> Self {
+        Self { line, column }
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
@@ -12,46 +65,153 @@ impl Chunk {
         Self {
             code: Vec::new(), 
             constants: Vec::new(),
+            constant_indices: HashMap::new(),
             lines: Vec::new()
         }
     }
 
-    pub fn write(&mut self, op: u8, line: u16) {
+    pub fn write(&mut self, op: u8, position: Position) {
+        // Only start a new run when the position changes
+        if self.lines.last().is_none_or(|run| run.position != position) {
+            self.lines.push(LineRun { start: self.code.len(), position });
+        }
         self.code.push(op);
-        self.lines.push(line);
     }
 
     pub fn write0(&mut self, op: u8) {
-        self.write(op, 0);
+        self.write(op, Position::default());
     }
 
-    pub fn write_f64(&mut self, float: f64, line: u16) {
-        self.write(OP_F64, line);
-        f64::to_be_bytes(float).iter().for_each(|b| self.write(*b, line));
+    pub fn write_f64(&mut self, float: f64, position: Position) {
+        self.write(OP_F64, position);
+        f64::to_be_bytes(float).iter().for_each(|b| self.write(*b, position));
     }
 
     pub fn write_f64_0(&mut self, float: f64) {
-        self.write_f64(float, 0)
+        self.write_f64(float, Position::default())
     }
     
-    pub fn write_constant(&mut self, value: Value, line: u16) -> Result<(), String> {
+    /// Writes an instruction loading the constant, using OP_CONSTANT_LONG once the index no longer fits in a byte
+    pub fn write_constant(&mut self, value: Value, position: Position) -> Result<(), String> {
+        let constant_index = self.add_constant(value)?;
+        self.write_indexed(OP_CONTANT, constant_index, position);
+        Ok(())
+    }
+
+    /// Writes an instruction with a byte operand, or the long variant of the instruction with a 24 bit operand
+    /// if `index` doesn't fit in a byte. Only instructions taking a constant index have a long variant.
+    pub fn write_indexed(&mut self, op: u8, index: usize, position: Position) {
+        if let Ok(index) = u8::try_from(index) {
+            self.write(op, position);
+            self.write(index, position);
+            return;
+        }
+
+        let long_op = long_variant(op).unwrap_or_else(|| panic!("{} has no long variant", INSTRUCTION_NAMES[op as usize]));
+        self.write(long_op, position);
+        let [_, high, middle, low] = (index as u32).to_be_bytes();
+        self.write(high, position);
+        self.write(middle, position);
+        self.write(low, position);
+    }
+
+    /// Adds a value to the constant pool without emitting any code, reusing an identical constant already in the pool
+    pub fn add_constant(&mut self, value: Value) -> Result<usize, String> {
+        let existing = match constant_key(&value) {
+            Some(key) => self.constant_indices.get(&key).copied(),
+            None => self.constants.iter().position(|constant| constant == &value),
+        };
+
+        match existing {
+            Some(constant_index) => Ok(constant_index),
+            None => self.push_constant(value),
+        }
+    }
+
+    /// Appends a value to the constant pool even if the pool already holds an identical constant
+    pub fn push_constant(&mut self, value: Value) -> Result<usize, String> {
         let constant_index = self.constants.len();
-        
-        if constant_index > 255 { 
-            return Err("More than 255 constants".to_string());
+
+        if constant_index > MAX_CONSTANT_INDEX {
+            return Err(format!("More than {} constants", MAX_CONSTANT_INDEX + 1));
+        }
+
+        if let Some(key) = constant_key(&value) {
+            self.constant_indices.entry(key).or_insert(constant_index);
         }
-        
         self.constants.push(value);
-        self.write(OP_CONTANT, line);
-        self.write(constant_index as u8, line);
-        Ok(())
+        Ok(constant_index)
     }
+
     
-    pub fn load_constant(&self, index: u8) -> Value {
-        self.constants[index as usize].clone()
+    /// Writes a jump with a placeholder offset and returns the position of the offset for [Chunk::patch_jump]
+    pub fn write_jump(&mut self, op: u8, position: Position) -> usize {
+        self.write(op, position);
+        self.write(0xff, position);
+        self.write(0xff, position);
+        self.code.len() - 2
+    }
+
+    /// Makes the jump whose offset is at `offset` land at the end of the code written so far
+    pub fn patch_jump(&mut self, offset: usize) -> Result<(), String> {
+        let jump = self.code.len() - offset - 2;
+
+        if jump > u16::MAX as usize {
+            return Err("Too much code to jump over".to_string());
+        }
+
+        self.code[offset..offset + 2].copy_from_slice(&(jump as u16).to_be_bytes());
+        Ok(())
+    }
+
+    /// Writes a backwards jump to `loop_start`
+    pub fn write_loop(&mut self, loop_start: usize, position: Position) -> Result<(), String> {
+        self.write(OP_LOOP, position);
+        let offset = self.code.len() - loop_start + 2;
+
+        if offset > u16::MAX as usize {
+            return Err("Loop body too large".to_string());
+        }
+
+        let [high, low] = (offset as u16).to_be_bytes();
+        self.write(high, position);
+        self.write(low, position);
+        Ok(())
+    }
+
+    pub fn load_constant(&self, index: usize) -> Value {
+        self.constants[index].clone()
     }
     
-    pub fn get_line(&self, index: usize) -> u16 {
-        self.lines[index]
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    /// Finds the source position of the code byte at `offset`
+    pub fn position_at(&self, offset: usize) -> Position {
+        let run = self.lines.partition_point(|run| run.start <= offset);
+        assert!(run > 0 && offset < self.code.len(), "Offset {} is outside of the code", offset);
+        self.lines[run - 1].position
     }
-}
\ No newline at end of file
+}
+
+/// The key under which a number or string constant is indexed, while other constants are found by comparing them
+fn constant_key(value: &Value) -> Option<ConstantKey> {
+    match value {
+        Value::Number(number) => Some(ConstantKey::Number(number.to_bits())),
+        Value::Obj(Obj::StringObj { value }) => Some(ConstantKey::String(value.clone())),
+        _ => None,
+    }
+}
+
+/// The variant of an instruction with a 24 bit constant index instead of a byte
+pub fn long_variant(op: u8) -> Option<u8> {
+    match op {
+        OP_CONTANT => Some(OP_CONSTANT_LONG),
+        OP_GET_GLOBAL => Some(OP_GET_GLOBAL_LONG),
+        OP_SET_GLOBAL => Some(OP_SET_GLOBAL_LONG),
+        OP_DEFINE_GLOBAL => Some(OP_DEFINE_GLOBAL_LONG),
+        OP_CLOSURE => Some(OP_CLOSURE_LONG),
+        _ => None,
+    }
+}
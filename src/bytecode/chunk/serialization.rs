@@ -0,0 +1,319 @@
+use crate::bytecode::chunk::{Chunk, LineRun, Position, MAX_FUNCTION_DEPTH};
+use crate::vm::value::{Function, Obj, Value};
+use std::rc::Rc;
+
+/// Every serialized chunk starts with these bytes
+pub const MAGIC: &[u8; 4] = b"FOPS";
+/// Bumped whenever the serialized format changes in an incompatible way
+pub const FORMAT_VERSION: u16 = 2;
+
+const TAG_NUMBER: u8 = 0x00;
+const TAG_STRING: u8 = 0x01;
+const TAG_FUNCTION: u8 = 0x02;
+const TAG_BOOL: u8 = 0x03;
+const TAG_NIL: u8 = 0x04;
+
+/// The serialized format is as follows, with all integers in big endian:
+///
+/// ```text
+/// file     → MAGIC FORMAT_VERSION:u16 chunk
+/// chunk    → code_len:u32 code:u8* run_count:u32 run* constant_count:u32 constant*
+/// run      → start:u32 line:u32 column:u32
+/// constant → TAG_NUMBER f64
+///          | TAG_STRING string
+///          | TAG_FUNCTION string arity:u8 upvalue_count:u8 chunk
+///          | TAG_BOOL u8
+///          | TAG_NIL
+/// string   → len:u32 utf8:u8*
+/// ```
+///
+/// The runs of the line table start at increasing offsets, the first one at offset 0,
+/// and functions are nested at most [MAX_FUNCTION_DEPTH] deep.
+impl Chunk {
+    /// Serializes the chunk along with every function nested in its constants
+    pub fn serialize(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        self.write_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, String> {
+        let mut reader = Reader { bytes, position: 0, depth: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("Not a fops bytecode file".to_string());
+        }
+
+        let version = reader.read_u16()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("Unsupported bytecode version {}, expected {}", version, FORMAT_VERSION));
+        }
+
+        let chunk = reader.read_chunk()?;
+        if reader.position != bytes.len() {
+            return Err(format!("Unexpected trailing data at byte {}", reader.position));
+        }
+        Ok(chunk)
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) -> Result<(), String> {
+        write_u32(bytes, self.code.len())?;
+        bytes.extend_from_slice(&self.code);
+        write_u32(bytes, self.lines.len())?;
+        for run in &self.lines {
+            write_u32(bytes, run.start)?;
+            bytes.extend_from_slice(&run.position.line.to_be_bytes());
+            bytes.extend_from_slice(&run.position.column.to_be_bytes());
+        }
+
+        write_u32(bytes, self.constants.len())?;
+        for constant in &self.constants {
+            match constant {
+                Value::Number(number) => {
+                    bytes.push(TAG_NUMBER);
+                    bytes.extend_from_slice(&number.to_be_bytes());
+                }
+                Value::Obj(Obj::StringObj { value }) => {
+                    bytes.push(TAG_STRING);
+                    write_string(bytes, value)?;
+                }
+                Value::Obj(Obj::Function(function)) => {
+                    bytes.push(TAG_FUNCTION);
+                    write_string(bytes, &function.name)?;
+                    bytes.push(function.arity);
+                    bytes.push(function.upvalue_count);
+                    function.chunk.write_to(bytes)?;
+                }
+                Value::Bool(bool) => {
+                    bytes.push(TAG_BOOL);
+                    bytes.push(*bool as u8);
+                }
+                Value::Nil => bytes.push(TAG_NIL),
+                other => return Err(format!("Can't serialize constant {}", other)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) -> Result<(), String> {
+    let value = u32::try_from(value).map_err(|_| "Chunk too large to serialize".to_string())?;
+    bytes.extend_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) -> Result<(), String> {
+    write_u32(bytes, string.len())?;
+    bytes.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// How many function constants the chunk being read is nested in
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("Unexpected end of bytecode at byte {}", self.position))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a count, or an offset into the code
+    fn read_length(&mut self) -> Result<usize, String> {
+        Ok(self.read_u32()? as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, String> {
+        let length = self.read_length()?;
+        let position = self.position;
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| format!("Invalid UTF-8 in string at byte {}", position))
+    }
+
+    fn read_chunk(&mut self) -> Result<Chunk, String> {
+        let code_length = self.read_length()?;
+        let code = self.take(code_length)?.to_vec();
+
+        let run_count = self.read_length()?;
+        let mut lines: Vec<LineRun> = Vec::new();
+        for _ in 0..run_count {
+            let position = self.position;
+            let start = self.read_length()?;
+            let line = self.read_u32()?;
+            let column = self.read_u32()?;
+
+            let in_order = lines.last().map_or(start == 0, |previous| previous.start < start);
+            if !in_order || start >= code_length {
+                return Err(format!("Line table run starting at {} is out of order at byte {}", start, position));
+            }
+            lines.push(LineRun { start, position: Position::new(line, column) });
+        }
+        if code_length > 0 && lines.is_empty() {
+            return Err("Missing line table".to_string());
+        }
+
+        let mut chunk = Chunk { code, lines, ..Chunk::new() };
+        let constant_count = self.read_length()?;
+        for _ in 0..constant_count {
+            let position = self.position;
+            let constant = match self.read_u8()? {
+                TAG_NUMBER => Value::Number(f64::from_be_bytes(self.take(8)?.try_into().unwrap())),
+                TAG_STRING => Value::from(self.read_string()?),
+                TAG_FUNCTION => {
+                    let name = self.read_string()?;
+                    let arity = self.read_u8()?;
+                    let upvalue_count = self.read_u8()?;
+                    if self.depth == MAX_FUNCTION_DEPTH {
+                        return Err(format!("Functions nested more than {} deep at byte {}", MAX_FUNCTION_DEPTH, position));
+                    }
+                    self.depth += 1;
+                    let chunk = self.read_chunk()?;
+                    self.depth -= 1;
+                    Value::Obj(Obj::Function(Rc::new(Function { name, arity, upvalue_count, chunk })))
+                }
+                TAG_BOOL => Value::Bool(self.read_u8()? != 0),
+                TAG_NIL => Value::Nil,
+                tag => return Err(format!("Unknown constant tag {:#04x} at byte {}", tag, position)),
+            };
+            chunk.push_constant(constant)?;
+        }
+
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::chunk::serialization::{FORMAT_VERSION, MAGIC};
+    use crate::bytecode::chunk::{Chunk, Position, MAX_FUNCTION_DEPTH};
+    use crate::bytecode::codes::*;
+    use crate::vm::value::{Function, Native, Obj, Value};
+    use std::rc::Rc;
+
+    fn header() -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut body = Chunk::new();
+        body.write_constant(Value::from("inner"), Position::new(3, 5)).unwrap();
+        body.write(OP_RETURN, Position::new(4, 1));
+        let function = Function { name: "f".to_string(), arity: 2, upvalue_count: 1, chunk: body };
+
+        let mut chunk = Chunk::new();
+        chunk.write_f64(1.5, Position::new(1, 1));
+        chunk.write_constant(Value::from("hello"), Position::new(1, 5)).unwrap();
+        chunk.write_constant(Value::Obj(Obj::Function(Rc::new(function))), Position::new(70000, 2)).unwrap();
+        chunk.write(OP_RETURN, Position::new(70001, 1));
+
+        let bytes = chunk.serialize().unwrap();
+        assert!(bytes.starts_with(&header()));
+        let copy = Chunk::deserialize(&bytes).unwrap();
+
+        assert_eq!(copy.code, chunk.code);
+        assert_eq!(copy.lines, chunk.lines);
+        assert_eq!(copy.load_constant(0), Value::from("hello"));
+        match copy.load_constant(1) {
+            Value::Obj(Obj::Function(function)) => {
+                assert_eq!(function.name, "f");
+                assert_eq!(function.arity, 2);
+                assert_eq!(function.upvalue_count, 1);
+                assert_eq!(function.chunk.code, vec![OP_CONTANT, 0, OP_RETURN]);
+                assert_eq!(function.chunk.position_at(1), Position::new(3, 5));
+                assert_eq!(function.chunk.position_at(2), Position::new(4, 1));
+                assert_eq!(function.chunk.load_constant(0), Value::from("inner"));
+            }
+            other => panic!("Expected function constant, got {}", other),
+        }
+    }
+
+    #[test]
+    fn reject_bad_header() {
+        let bytes = Chunk::new().serialize().unwrap();
+        assert!(Chunk::deserialize(&bytes).is_ok());
+        assert!(Chunk::deserialize(&bytes[4..]).is_err());
+        assert!(Chunk::deserialize(b"").is_err());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[5] += 1;
+        assert!(Chunk::deserialize(&wrong_version).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_chunks() {
+        let bytes = Chunk::new().serialize().unwrap();
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Chunk::deserialize(&trailing).is_err());
+
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::from("truncated"), Position::new(1, 1)).unwrap();
+        let bytes = chunk.serialize().unwrap();
+        assert!(Chunk::deserialize(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut unknown_tag = header();
+        unknown_tag.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff]);
+        assert!(Chunk::deserialize(&unknown_tag).is_err());
+
+        // A single byte of code whose only line table run starts after it
+        let mut bad_lines = header();
+        bad_lines.extend_from_slice(&[0, 0, 0, 1, OP_NIL, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert!(Chunk::deserialize(&bad_lines).is_err());
+        bad_lines[18] = 0;
+        assert!(Chunk::deserialize(&bad_lines).is_ok());
+
+        let mut missing_lines = header();
+        missing_lines.extend_from_slice(&[0, 0, 0, 1, OP_NIL, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(Chunk::deserialize(&missing_lines).is_err());
+    }
+
+    #[test]
+    fn reject_deep_nesting() {
+        // Every level is an empty chunk holding a single function constant, with the innermost chunk empty
+        let nested = |depth: usize| {
+            let mut bytes = header();
+            for _ in 0..depth {
+                bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0x02, 0, 0, 0, 0, 0, 0]);
+            }
+            bytes.extend_from_slice(&[0; 12]);
+            bytes
+        };
+
+        assert!(Chunk::deserialize(&nested(MAX_FUNCTION_DEPTH)).is_ok());
+        let error = Chunk::deserialize(&nested(MAX_FUNCTION_DEPTH + 1)).unwrap_err();
+        assert!(error.starts_with("Functions nested more than 256 deep"), "{}", error);
+        assert!(Chunk::deserialize(&nested(100_000)).is_err());
+    }
+
+    #[test]
+    fn reject_natives() {
+        let native = Native { name: "native".to_string(), arity: 0, function: Box::new(|_, _| Ok(Value::Nil)) };
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Obj(Obj::Native(Rc::new(native)))).unwrap();
+        assert!(chunk.serialize().is_err());
+    }
+}
@@ -1,38 +1,231 @@
+use crate::bytecode::chunk::Chunk;
 use crate::bytecode::codes::*;
+use crate::vm::value::{Obj, Value};
+use std::fmt::Write;
 
-pub fn disassemble(instructions: Vec<u8>) {
-    let mut index = 0;
-    while index < instructions.len() {
-        let code = &instructions[index];
-
-        let name = INSTRUCTION_NAMES[*code as usize];
-        let instruction_length = INSTRUCTION_LENGTH[*code as usize];
-        let arg_from = index + 1;
-        let arg_to = index + instruction_length as usize;
-        let arguments = &instructions[arg_from..arg_to];
-
-        if instruction_length == 1 {
-            print_simple(&index, name)
-        } else {
-            match *code {
-                OP_F64 => print_f32(&index, name, arguments),
-                _ => panic!("Unknown opcode {:#04x}", code),
-            }
+/// Renders the chunk as one instruction per line, followed by every function nested in its constants.
+///
+/// Nested functions are headed by their name and arity.
+/// Each line holds the offset, the source line and column, the instruction name and its decoded operands.
+/// As in clox, the line is replaced by `|` when it is the same as the previous byte's, though the column is still shown.
+/// Malformed code is rendered rather than rejected.
+pub fn disassemble(chunk: &Chunk, name: &str) -> String {
+    let mut output = String::new();
+    disassemble_into(&mut output, chunk, name);
+    output
+}
+
+fn disassemble_into(output: &mut String, chunk: &Chunk, header: &str) {
+    writeln!(output, "== {} ==", header).unwrap();
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(output, chunk, offset);
+    }
+
+    for constant in chunk.constants() {
+        if let Value::Obj(Obj::Function(function)) = constant {
+            writeln!(output).unwrap();
+            disassemble_into(output, &function.chunk, &format!("{} (arity {})", function.name, function.arity));
+        }
+    }
+}
+
+/// Renders the instruction at `offset` and returns the offset of the next instruction
+pub fn disassemble_instruction(output: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    let code = &chunk.code;
+    write!(output, "{:04} ", offset).unwrap();
+    let position = chunk.position_at(offset);
+    if offset > 0 && position.line == chunk.position_at(offset - 1).line {
+        write!(output, "{:>7} ", format!("|:{}", position.column)).unwrap();
+    } else {
+        write!(output, "{:>7} ", position.to_string()).unwrap();
+    }
+
+    let opcode = code[offset];
+    let name = INSTRUCTION_NAMES[opcode as usize];
+    let length = INSTRUCTION_LENGTH[opcode as usize] as usize;
+    if length == 0 {
+        writeln!(output, "Unknown opcode {:#04x}", opcode).unwrap();
+        return offset + 1;
+    }
+    if offset + length > code.len() {
+        writeln!(output, "{:<16} <truncated>", name).unwrap();
+        return code.len();
+    }
+
+    let operand = code.get(offset + 1).copied().unwrap_or_default();
+    // Long variants of the instructions taking a constant index have a 24 bit operand
+    let index = if length == 4 {
+        u32::from_be_bytes([0, code[offset + 1], code[offset + 2], code[offset + 3]]) as usize
+    } else {
+        operand as usize
+    };
+    match opcode {
+        OP_F64 => {
+            let value = f64::from_be_bytes(code[offset + 1..offset + 9].try_into().unwrap());
+            writeln!(output, "{:<16} {:4}", name, value).unwrap();
         }
+        OP_CONTANT | OP_CONSTANT_LONG | OP_GET_GLOBAL | OP_GET_GLOBAL_LONG | OP_SET_GLOBAL | OP_SET_GLOBAL_LONG
+        | OP_DEFINE_GLOBAL | OP_DEFINE_GLOBAL_LONG => {
+            writeln!(output, "{:<16} {:4} {}", name, index, constant(chunk, index)).unwrap();
+        }
+        OP_GET_LOCAL | OP_SET_LOCAL | OP_GET_UPVALUE | OP_SET_UPVALUE | OP_CALL => {
+            writeln!(output, "{:<16} {:4}", name, operand).unwrap();
+        }
+        OP_JUMP | OP_JUMP_IF_FALSE | OP_LOOP => {
+            let jump = u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as isize;
+            let sign = if opcode == OP_LOOP { -1 } else { 1 };
+            let target = offset as isize + 3 + sign * jump;
+            writeln!(output, "{:<16} {:4} -> {}", name, offset, target).unwrap();
+        }
+        OP_CLOSURE | OP_CLOSURE_LONG => {
+            writeln!(output, "{:<16} {:4} {}", name, index, constant(chunk, index)).unwrap();
+            let upvalue_count = match chunk.constants().get(index) {
+                Some(Value::Obj(Obj::Function(function))) => function.upvalue_count as usize,
+                _ => 0,
+            };
 
-        index += instruction_length as usize;
+            let mut next = offset + length;
+            for _ in 0..upvalue_count {
+                if next + 2 > code.len() {
+                    writeln!(output, "{:04} {:>7} {:<16} <truncated>", next, "|", "").unwrap();
+                    return code.len();
+                }
+                let kind = if code[next] == 1 { "local" } else { "upvalue" };
+                writeln!(output, "{:04} {:>7} {:<16} {} {}", next, "|", "", kind, code[next + 1]).unwrap();
+                next += 2;
+            }
+            return next;
+        }
+        _ => {
+            writeln!(output, "{}", name).unwrap();
+        }
     }
+
+    offset + length
 }
 
-fn print_simple(index: &usize, name: &str) {
-    println!("{:#04x} {}", index, name);
+/// Renders a constant, quoting strings so that their contents can't break up the output
+fn constant(chunk: &Chunk, index: usize) -> String {
+    match chunk.constants().get(index) {
+        Some(Value::Obj(Obj::StringObj { value })) => format!("{:?}", value),
+        Some(value) => format!("{}", value),
+        None => "<out of bounds>".to_string(),
+    }
 }
 
-fn print_f32(index: &usize, name: &str, arg: &[u8]) {
-    println!(
-        "{:#04x} {} {}",
-        index,
-        name,
-        f32::from_be_bytes(arg.try_into().unwrap())
-    );
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::chunk::{Chunk, Position};
+    use crate::bytecode::codes::*;
+    use crate::bytecode::disassembler::disassemble;
+    use crate::vm::value::{Function, Obj, Value};
+    use std::rc::Rc;
+
+    fn disassemble_source(source: &str) -> String {
+        let chunk = crate::compiler::compile(source, false).unwrap();
+        disassemble(&chunk, "script")
+    }
+
+    #[test]
+    fn constants_and_lines() {
+        let source = "let greeting = \"Hello\\n\";\nprintln(greeting + \"!\");\n-1.5;";
+        assert_eq!(disassemble_source(source), "\
+== script ==
+0000    1:16 OP_CONTANT          1 \"Hello\\n\"
+0002    |:25 OP_DEFINE_GLOBAL    0 \"greeting\"
+0004     2:1 OP_GET_GLOBAL       2 \"println\"
+0006     |:9 OP_GET_GLOBAL       0 \"greeting\"
+0008    |:20 OP_CONTANT          3 \"!\"
+0010    |:18 OP_ADD
+0011    |:23 OP_CALL             1
+0013    |:24 OP_POP
+0014     3:2 OP_F64            1.5
+0023     |:1 OP_NEGATE
+0024     |:5 OP_POP
+");
+    }
+
+    #[test]
+    fn jumps() {
+        let source = "let a = true;\nwhile (a) {\n  a = false;\n}";
+        assert_eq!(disassemble_source(source), "\
+== script ==
+0000     1:9 OP_TRUE
+0001    |:13 OP_DEFINE_GLOBAL    0 \"a\"
+0003     2:8 OP_GET_GLOBAL       0 \"a\"
+0005     |:9 OP_JUMP_IF_FALSE    5 -> 16
+0008     |:9 OP_POP
+0009     3:7 OP_FALSE
+0010     |:7 OP_SET_GLOBAL       0 \"a\"
+0012    |:12 OP_POP
+0013     4:1 OP_LOOP            13 -> 3
+0016     |:1 OP_POP
+");
+    }
+
+    #[test]
+    fn nested_functions() {
+        let source = "fun outer(x) {\n  fun inner() { return x; }\n  return inner;\n}";
+        assert_eq!(disassemble_source(source), "\
+== script ==
+0000     4:1 OP_CLOSURE          1 <fun outer>
+0002     |:1 OP_DEFINE_GLOBAL    0 \"outer\"
+
+== outer (arity 1) ==
+0000    2:27 OP_CLOSURE          0 <fun inner>
+0002       |                  local 1
+0004    3:10 OP_GET_LOCAL        2
+0006    |:15 OP_RETURN
+0007     4:1 OP_NIL
+0008     |:1 OP_RETURN
+
+== inner (arity 0) ==
+0000    2:24 OP_GET_UPVALUE      0
+0002    |:25 OP_RETURN
+0003    |:27 OP_NIL
+0004    |:27 OP_RETURN
+");
+    }
+
+    #[test]
+    fn long_operands() {
+        let mut chunk = Chunk::new();
+        for i in 0..256 {
+            chunk.push_constant(Value::Number(i as f64)).unwrap();
+        }
+        chunk.push_constant(Value::from("f")).unwrap();
+        let function = Function { name: "f".to_string(), arity: 0, upvalue_count: 1, chunk: Chunk::new() };
+        chunk.push_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+
+        chunk.write_indexed(OP_CLOSURE, 257, Position::new(1, 2));
+        chunk.write(1, Position::new(1, 2));
+        chunk.write(0, Position::new(1, 2));
+        chunk.write_indexed(OP_DEFINE_GLOBAL, 256, Position::new(1, 3));
+        assert_eq!(disassemble(&chunk, "script"), "\
+== script ==
+0000     1:2 OP_CLOSURE_LONG   257 <fun f>
+0004       |                  local 0
+0006     |:3 OP_DEFINE_GLOBAL_LONG  256 \"f\"
+
+== f (arity 0) ==
+");
+    }
+
+    #[test]
+    fn malformed_code() {
+        let mut chunk = Chunk::new();
+        chunk.write(0xff, Position::new(1, 1));
+        chunk.write(OP_CONTANT, Position::new(1, 1));
+        chunk.write(7, Position::new(1, 1));
+        chunk.write(OP_JUMP, Position::new(2, 1));
+        chunk.write(0, Position::new(2, 1));
+        assert_eq!(disassemble(&chunk, "broken"), "\
+== broken ==
+0000     1:1 Unknown opcode 0xff
+0001     |:1 OP_CONTANT          7 <out of bounds>
+0003     2:1 OP_JUMP          <truncated>
+");
+    }
 }
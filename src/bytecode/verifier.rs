@@ -0,0 +1,456 @@
+use crate::bytecode::chunk::{Chunk, MAX_FUNCTION_DEPTH};
+use crate::bytecode::codes::*;
+use crate::vm::value::{Obj, Value};
+
+/// Checks that a chunk is safe to execute, along with every function nested in its constants.
+///
+/// Every instruction must be a known opcode with all of its operands inside the chunk,
+/// constant operands must refer to constants of the right type, and jumps must land on an instruction.
+/// The stack depth is tracked through every path of the code, so no instruction may pop more than
+/// was pushed in its frame, access a local slot which doesn't exist, or be reachable with different depths.
+pub fn verify(chunk: &Chunk) -> Result<(), String> {
+    verify_function("script", chunk, 0, 0, 0)
+}
+
+/// Verifies the body of a function, whose frame starts out with `initial_depth` values on the stack,
+/// and then each function constant of its chunk once. `nesting` is how many functions the chunk is nested in.
+fn verify_function(name: &str, chunk: &Chunk, initial_depth: usize, upvalue_count: u8, nesting: usize) -> Result<(), String> {
+    let verifier = Verifier { name, chunk, upvalue_count };
+    let starts = verifier.decode()?;
+    verifier.check_stack(&starts, initial_depth)?;
+
+    for constant in chunk.constants() {
+        if let Value::Obj(Obj::Function(function)) = constant {
+            if nesting == MAX_FUNCTION_DEPTH {
+                return Err(format!("Invalid bytecode in {}: functions nested more than {} deep", name, MAX_FUNCTION_DEPTH));
+            }
+            let frame_depth = function.arity as usize + 1;
+            verify_function(&function.name, &function.chunk, frame_depth, function.upvalue_count, nesting + 1)?;
+        }
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    name: &'a str,
+    chunk: &'a Chunk,
+    upvalue_count: u8,
+}
+
+/// How an instruction affects the stack and where execution continues
+struct Effect {
+    pops: usize,
+    pushes: usize,
+    falls_through: bool,
+    jump: Option<usize>,
+}
+
+impl Effect {
+    fn new(pops: usize, pushes: usize) -> Self {
+        Effect { pops, pushes, falls_through: true, jump: None }
+    }
+}
+
+impl Verifier<'_> {
+    fn error(&self, offset: usize, message: impl AsRef<str>) -> String {
+        format!("Invalid bytecode in {} at offset {}: {}", self.name, offset, message.as_ref())
+    }
+
+    /// The constant index operand of the instruction at `offset`, which is 24 bits for the long variants
+    fn constant_index(&self, offset: usize) -> usize {
+        let code = &self.chunk.code;
+        match INSTRUCTION_LENGTH[code[offset] as usize] {
+            4 => u32::from_be_bytes([0, code[offset + 1], code[offset + 2], code[offset + 3]]) as usize,
+            _ => code[offset + 1] as usize,
+        }
+    }
+
+    /// Total length of the instruction at `offset`, including the upvalue pairs of OP_CLOSURE
+    fn instruction_length(&self, offset: usize) -> Result<usize, String> {
+        let code = &self.chunk.code;
+        let opcode = code[offset];
+        let length = INSTRUCTION_LENGTH[opcode as usize] as usize;
+
+        if length == 0 {
+            return Err(self.error(offset, format!("Unknown opcode {:#04x}", opcode)));
+        }
+        if offset + length > code.len() {
+            return Err(self.error(offset, format!("Truncated operand for {}", INSTRUCTION_NAMES[opcode as usize])));
+        }
+
+        if opcode == OP_CLOSURE || opcode == OP_CLOSURE_LONG {
+            let upvalues = match self.chunk.constants().get(self.constant_index(offset)) {
+                Some(Value::Obj(Obj::Function(function))) => function.upvalue_count as usize,
+                _ => return Ok(length),
+            };
+            let full_length = length + 2 * upvalues;
+            if offset + full_length > code.len() {
+                return Err(self.error(offset, format!("Truncated upvalues for {}", INSTRUCTION_NAMES[opcode as usize])));
+            }
+            return Ok(full_length);
+        }
+        Ok(length)
+    }
+
+    /// Checks opcodes, operand bounds and constants, and returns which offsets start an instruction
+    fn decode(&self) -> Result<Vec<bool>, String> {
+        let code = &self.chunk.code;
+        let mut starts = vec![false; code.len() + 1];
+        let mut offset = 0;
+
+        while offset < code.len() {
+            starts[offset] = true;
+            let length = self.instruction_length(offset)?;
+            self.check_constants(offset)?;
+            offset += length;
+        }
+
+        // Running off the end of the code is an implicit return, so the end is a valid jump target
+        starts[code.len()] = true;
+        Ok(starts)
+    }
+
+    fn check_constants(&self, offset: usize) -> Result<(), String> {
+        let code = &self.chunk.code;
+        let constants = self.chunk.constants();
+        let constant = || {
+            let index = self.constant_index(offset);
+            constants.get(index)
+                .ok_or_else(|| self.error(offset, format!("Constant index {} out of bounds for {} constants", index, constants.len())))
+        };
+
+        match code[offset] {
+            OP_CONTANT | OP_CONSTANT_LONG => {
+                constant()?;
+            }
+            OP_GET_GLOBAL | OP_GET_GLOBAL_LONG | OP_SET_GLOBAL | OP_SET_GLOBAL_LONG | OP_DEFINE_GLOBAL
+            | OP_DEFINE_GLOBAL_LONG => match constant()? {
+                Value::Obj(Obj::StringObj { .. }) => {}
+                other => return Err(self.error(offset, format!("Expected global name to be a string, got {}", other.type_name()))),
+            },
+            OP_CLOSURE | OP_CLOSURE_LONG => match constant()? {
+                Value::Obj(Obj::Function(function)) => {
+                    let pairs_start = offset + INSTRUCTION_LENGTH[code[offset] as usize] as usize;
+                    for pair in 0..function.upvalue_count as usize {
+                        let is_local = code[pairs_start + 2 * pair];
+                        let index = code[pairs_start + 1 + 2 * pair];
+                        match is_local {
+                            0 if index >= self.upvalue_count => {
+                                return Err(self.error(offset, format!("Upvalue index {} out of bounds for {} upvalues", index, self.upvalue_count)));
+                            }
+                            0 | 1 => {}
+                            _ => return Err(self.error(offset, format!("Invalid upvalue kind {}", is_local))),
+                        }
+                    }
+                }
+                other => return Err(self.error(offset, format!("Expected function constant, got {}", other.type_name()))),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn effect(&self, offset: usize, depth: usize) -> Result<Effect, String> {
+        let code = &self.chunk.code;
+        let operand = || code[offset + 1] as usize;
+        let jump_operand = || u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        let local = |slot: usize| {
+            if slot < depth {
+                Ok(())
+            } else {
+                Err(self.error(offset, format!("Local slot {} out of bounds for stack depth {}", slot, depth)))
+            }
+        };
+        let upvalue = |index: usize| {
+            if index < self.upvalue_count as usize {
+                Ok(())
+            } else {
+                Err(self.error(offset, format!("Upvalue index {} out of bounds for {} upvalues", index, self.upvalue_count)))
+            }
+        };
+
+        let effect = match code[offset] {
+            OP_F64 | OP_NIL | OP_TRUE | OP_FALSE | OP_CONTANT | OP_CONSTANT_LONG => Effect::new(0, 1),
+            OP_GET_GLOBAL | OP_GET_GLOBAL_LONG => Effect::new(0, 1),
+            OP_CLOSURE | OP_CLOSURE_LONG => {
+                let pairs_start = offset + INSTRUCTION_LENGTH[code[offset] as usize] as usize;
+                let pairs = (offset + self.instruction_length(offset)? - pairs_start) / 2;
+                for pair in 0..pairs {
+                    if code[pairs_start + 2 * pair] == 1 {
+                        local(code[pairs_start + 1 + 2 * pair] as usize)?;
+                    }
+                }
+                Effect::new(0, 1)
+            }
+            OP_ADD | OP_SUBTRACT | OP_DIVIDE | OP_MULTIPLY => Effect::new(2, 1),
+            OP_EQUALS | OP_NOT_EQUALS | OP_LESS_THAN | OP_LESS_THAN_OR_EQUALS
+            | OP_GREATER_THAN | OP_GREATER_THAN_OR_EQUALS => Effect::new(2, 1),
+            OP_NEGATE | OP_NOT | OP_SET_GLOBAL | OP_SET_GLOBAL_LONG => Effect::new(1, 1),
+            OP_POP | OP_DEFINE_GLOBAL | OP_DEFINE_GLOBAL_LONG | OP_CLOSE_UPVALUE => Effect::new(1, 0),
+            OP_RETURN => Effect { falls_through: false, ..Effect::new(1, 0) },
+            OP_GET_LOCAL => {
+                local(operand())?;
+                Effect::new(0, 1)
+            }
+            OP_SET_LOCAL => {
+                local(operand())?;
+                Effect::new(1, 1)
+            }
+            OP_GET_UPVALUE => {
+                upvalue(operand())?;
+                Effect::new(0, 1)
+            }
+            OP_SET_UPVALUE => {
+                upvalue(operand())?;
+                Effect::new(1, 1)
+            }
+            OP_CALL => Effect::new(operand() + 1, 1),
+            OP_JUMP => Effect { falls_through: false, jump: Some(offset + 3 + jump_operand()), ..Effect::new(0, 0) },
+            OP_JUMP_IF_FALSE => Effect { jump: Some(offset + 3 + jump_operand()), ..Effect::new(1, 1) },
+            OP_LOOP => {
+                let target = (offset + 3).checked_sub(jump_operand())
+                    .ok_or_else(|| self.error(offset, "Loop jumps before the start of the code"))?;
+                Effect { falls_through: false, jump: Some(target), ..Effect::new(0, 0) }
+            }
+            opcode => unreachable!("Opcode {:#04x} was already decoded", opcode),
+        };
+        Ok(effect)
+    }
+
+    /// Follows every path through the code, checking that the stack depth never goes negative
+    /// and that every instruction is reached with the same depth
+    fn check_stack(&self, starts: &[bool], initial_depth: usize) -> Result<(), String> {
+        let code_length = self.chunk.code.len();
+        let mut depths: Vec<Option<usize>> = vec![None; code_length + 1];
+        let mut pending = vec![(0, initial_depth)];
+
+        while let Some((offset, depth)) = pending.pop() {
+            match depths[offset] {
+                Some(known) if known == depth => continue,
+                Some(known) => {
+                    return Err(self.error(offset, format!("Reached with stack depth {} and {}", known, depth)));
+                }
+                None => depths[offset] = Some(depth),
+            }
+            if offset == code_length {
+                continue;
+            }
+
+            let effect = self.effect(offset, depth)?;
+            if effect.pops > depth {
+                let name = INSTRUCTION_NAMES[self.chunk.code[offset] as usize];
+                return Err(self.error(offset, format!("{} pops {} values from a stack of {}", name, effect.pops, depth)));
+            }
+            let new_depth = depth - effect.pops + effect.pushes;
+
+            if let Some(target) = effect.jump {
+                if !starts.get(target).copied().unwrap_or(false) {
+                    return Err(self.error(offset, format!("Jump target {} is not the start of an instruction", target)));
+                }
+                pending.push((target, new_depth));
+            }
+            if effect.falls_through {
+                pending.push((offset + self.instruction_length(offset)?, new_depth));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::chunk::{Chunk, MAX_FUNCTION_DEPTH};
+    use crate::bytecode::codes::*;
+    use crate::bytecode::verifier::verify;
+    use crate::vm::value::{Function, Obj, Value};
+    use std::rc::Rc;
+
+    fn chunk(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        code.iter().for_each(|byte| chunk.write0(*byte));
+        chunk
+    }
+
+    fn assert_invalid(chunk: &Chunk, message: &str) {
+        match verify(chunk) {
+            Ok(()) => panic!("Expected verification to fail with {:?}", message),
+            Err(error) => assert!(error.contains(message), "Expected {:?} to contain {:?}", error, message),
+        }
+    }
+
+    fn function(arity: u8, upvalue_count: u8, code: &[u8]) -> Value {
+        let function = Function { name: "f".to_string(), arity, upvalue_count, chunk: chunk(code) };
+        Value::Obj(Obj::Function(Rc::new(function)))
+    }
+
+    /// Functions nested `depth` deep, where every chunk creates a closure of the function inside it `closures` times
+    fn nested(depth: usize, closures: usize) -> Chunk {
+        let mut chunk = Chunk::new();
+        for _ in 0..depth {
+            let inner = Function { name: "f".to_string(), arity: 0, upvalue_count: 0, chunk };
+            chunk = Chunk::new();
+            chunk.push_constant(Value::Obj(Obj::Function(Rc::new(inner)))).unwrap();
+            for _ in 0..closures {
+                chunk.write0(OP_CLOSURE);
+                chunk.write0(0);
+                chunk.write0(OP_POP);
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn compiled_code() {
+        let source = "
+            let a = 1;
+            fun f(x) {
+                let y = x;
+                fun g() { return y + a; }
+                repeat (3) { if (y > 1 && true) break; else continue; }
+                while (false) {}
+                return g;
+            }
+            f(2)();
+        ";
+        let chunk = crate::compiler::compile(source, false).unwrap();
+        assert_eq!(verify(&chunk), Ok(()));
+    }
+
+    #[test]
+    fn empty_chunk() {
+        assert_eq!(verify(&Chunk::new()), Ok(()));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        assert_invalid(&chunk(&[0x0A]), "Unknown opcode 0x0a");
+        assert_invalid(&chunk(&[0xff]), "Unknown opcode 0xff");
+    }
+
+    #[test]
+    fn truncated_operands() {
+        assert_invalid(&chunk(&[OP_F64, 0, 0, 0]), "Truncated operand for OP_F64");
+        assert_invalid(&chunk(&[OP_JUMP, 0]), "Truncated operand for OP_JUMP");
+        assert_invalid(&chunk(&[OP_NIL, OP_GET_LOCAL]), "Truncated operand for OP_GET_LOCAL");
+
+        let mut closure = Chunk::new();
+        let index = closure.add_constant(function(0, 1, &[])).unwrap();
+        closure.write0(OP_CLOSURE);
+        closure.write0(index as u8);
+        closure.write0(1);
+        assert_invalid(&closure, "Truncated upvalues");
+    }
+
+    #[test]
+    fn constant_indices() {
+        assert_invalid(&chunk(&[OP_CONTANT, 0]), "Constant index 0 out of bounds");
+        assert_invalid(&chunk(&[OP_CONSTANT_LONG, 1, 0, 0]), "Constant index 65536 out of bounds");
+        assert_invalid(&chunk(&[OP_CONSTANT_LONG, 0, 0]), "Truncated operand for OP_CONSTANT_LONG");
+
+        let mut global = Chunk::new();
+        global.write_f64_0(1.0);
+        global.write0(OP_DEFINE_GLOBAL);
+        let index = global.add_constant(Value::Number(1.0)).unwrap();
+        global.write0(index as u8);
+        assert_invalid(&global, "Expected global name to be a string, got number");
+
+        let mut closure = Chunk::new();
+        closure.write0(OP_CLOSURE);
+        let index = closure.add_constant(Value::from("f")).unwrap();
+        closure.write0(index as u8);
+        assert_invalid(&closure, "Expected function constant, got string");
+    }
+
+    #[test]
+    fn long_operands() {
+        assert_invalid(&chunk(&[OP_GET_GLOBAL_LONG, 0, 0]), "Truncated operand for OP_GET_GLOBAL_LONG");
+        assert_invalid(&chunk(&[OP_NIL, OP_DEFINE_GLOBAL_LONG, 0, 1, 0]), "Constant index 256 out of bounds");
+
+        let mut closure = Chunk::new();
+        for i in 0..256 {
+            closure.push_constant(Value::Number(i as f64)).unwrap();
+        }
+        closure.push_constant(function(0, 1, &[])).unwrap();
+        closure.push_constant(Value::from("f")).unwrap();
+        closure.write_indexed(OP_CLOSURE, 256, Default::default());
+        closure.write0(1);
+        assert_invalid(&closure, "Truncated upvalues for OP_CLOSURE_LONG");
+        closure.write0(0);
+        assert_invalid(&closure, "Local slot 0 out of bounds");
+
+        let mut valid = closure.clone();
+        valid.code = vec![OP_NIL];
+        valid.write_indexed(OP_CLOSURE, 256, Default::default());
+        valid.write0(1);
+        valid.write0(0);
+        valid.write_indexed(OP_DEFINE_GLOBAL, 257, Default::default());
+        valid.write_indexed(OP_GET_GLOBAL, 257, Default::default());
+        valid.write_indexed(OP_SET_GLOBAL, 257, Default::default());
+        assert_eq!(verify(&valid), Ok(()));
+    }
+
+    #[test]
+    fn jump_targets() {
+        assert_eq!(verify(&chunk(&[OP_JUMP, 0, 1, OP_NIL])), Ok(()));
+        assert_invalid(&chunk(&[OP_JUMP, 0, 2, OP_NIL]), "Jump target 5 is not the start of an instruction");
+        assert_invalid(&chunk(&[OP_JUMP, 0, 1, OP_F64, 0, 0, 0, 0, 0, 0, 0, 0]), "Jump target 4");
+        assert_invalid(&chunk(&[OP_LOOP, 0, 4]), "before the start");
+        assert_invalid(&chunk(&[OP_NIL, OP_LOOP, 0, 2]), "Jump target 2");
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert_invalid(&chunk(&[OP_POP]), "OP_POP pops 1 values from a stack of 0");
+        assert_invalid(&chunk(&[OP_NIL, OP_ADD]), "OP_ADD pops 2 values from a stack of 1");
+        assert_invalid(&chunk(&[OP_NIL, OP_CALL, 1]), "OP_CALL pops 2 values");
+        assert_invalid(&chunk(&[OP_RETURN]), "OP_RETURN pops 1 values");
+    }
+
+    #[test]
+    fn inconsistent_stack_depth() {
+        assert_invalid(&chunk(&[OP_TRUE, OP_JUMP_IF_FALSE, 0, 1, OP_NIL, OP_POP]), "Reached with stack depth");
+        assert_invalid(&chunk(&[OP_NIL, OP_LOOP, 0, 4]), "Reached with stack depth");
+    }
+
+    #[test]
+    fn locals_and_upvalues() {
+        assert_invalid(&chunk(&[OP_GET_LOCAL, 0]), "Local slot 0 out of bounds");
+        assert_invalid(&chunk(&[OP_NIL, OP_SET_LOCAL, 1]), "Local slot 1 out of bounds");
+        assert_invalid(&chunk(&[OP_GET_UPVALUE, 0]), "Upvalue index 0 out of bounds");
+
+        // The function refers to a local slot above its parameter and callee
+        let mut closure = Chunk::new();
+        closure.write0(OP_CLOSURE);
+        let index = closure.add_constant(function(1, 0, &[OP_GET_LOCAL, 2])).unwrap();
+        closure.write0(index as u8);
+        assert_invalid(&closure, "in f at offset 0: Local slot 2 out of bounds");
+
+        let mut closure = Chunk::new();
+        closure.write0(OP_CLOSURE);
+        let index = closure.add_constant(function(0, 1, &[])).unwrap();
+        closure.write0(index as u8);
+        closure.write0(0);
+        closure.write0(0);
+        assert_invalid(&closure, "Upvalue index 0 out of bounds for 0 upvalues");
+
+        let mut closure = Chunk::new();
+        closure.write0(OP_CLOSURE);
+        let index = closure.add_constant(function(0, 1, &[])).unwrap();
+        closure.write0(index as u8);
+        closure.write0(1);
+        closure.write0(0);
+        assert_invalid(&closure, "Local slot 0 out of bounds");
+    }
+
+    #[test]
+    fn functions_are_verified_once() {
+        // Verifying the function again for every closure of it would take 20^40 steps
+        assert_eq!(verify(&nested(40, 20)), Ok(()));
+    }
+
+    #[test]
+    fn deep_nesting() {
+        assert_eq!(verify(&nested(MAX_FUNCTION_DEPTH, 1)), Ok(()));
+        assert_invalid(&nested(MAX_FUNCTION_DEPTH + 1, 1), "functions nested more than 256 deep");
+    }
+}
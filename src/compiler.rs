@@ -1,44 +1,92 @@
 #[cfg(test)]
 mod tests;
 
-use crate::bytecode::chunk::Chunk;
+use crate::bytecode::chunk::{Chunk, Position, MAX_FUNCTION_DEPTH};
 use crate::bytecode::codes::*;
 use crate::compiler::Precedence::*;
+use crate::diagnostic::{Diagnostic, Span};
 use crate::scanner::TokenType::*;
-use crate::scanner::{Scanner, Token, TokenType, PLACEHOLDER_TOKEN};
-use crate::vm::value::Value;
+use crate::scanner::{self, Scanner, Token, TokenType, PLACEHOLDER_TOKEN};
+use crate::vm::value::{Function, Obj, Value};
+use std::rc::Rc;
 use strum::VariantArray;
 
-pub(crate) fn compile(source: String, repl: bool) -> Result<Chunk, ()> {
-    let mut chunk = Chunk::new();
-    let mut parser = Parser::init(&source, &mut chunk, repl);
+/// Compiles the source into the chunk of its top-level script, or returns every diagnostic that was reported.
+///
+/// In `repl` mode, a trailing expression without a semicolon is returned by the script.
+pub fn compile(source: &str, repl: bool) -> Result<Chunk, Vec<Diagnostic>> {
+    let mut parser = Parser::init(source, repl);
 
     parser.advance();
     while !parser.match_token(EOF) {
         parser.declaration();
     }
 
-    match parser.had_error {
-        true => Err(()),
-        false => {
-            Ok(chunk)
-        }
+    let (script, _) = parser.end_compiler();
+    if parser.diagnostics.is_empty() {
+        Ok(script.chunk)
+    } else {
+        Err(parser.diagnostics)
     }
 }
 
 struct Parser<'a> {
     current: Token<'a>,
-    chunk: &'a mut Chunk,
     repl: bool,
     previous: Token<'a>,
     scanner: Scanner<'a>,
-    scope_depth: usize,
-    had_error: bool,
+    /// The function currently being compiled is last, preceded by the functions enclosing it
+    compilers: Vec<Compiler<'a>>,
+    diagnostics: Vec<Diagnostic>,
     panic_mode: bool,
+    /// The `{` of every block being compiled, innermost last
+    open_blocks: Vec<Token<'a>>,
     rules: Vec<ParseRule<'a>>,
 }
 
+/// State for compiling a single function body
+struct Compiler<'a> {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local<'a>>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+}
+
+struct Local<'a> {
+    name: Token<'a>,
+    /// [None] while the initializer is still being compiled
+    depth: Option<usize>,
+    /// Whether a closure refers to this local, so it must be moved off the stack when it goes out of scope
+    is_captured: bool,
+}
+
+/// Where a closure finds one of its upvalues when it is created
+#[derive(Copy, Clone)]
+struct UpvalueRef {
+    /// Local slot of the enclosing function if `is_local`, otherwise an upvalue index of the enclosing function
+    index: u8,
+    is_local: bool,
+}
+
+struct Loop {
+    /// Where `continue` jumps back to
+    start: usize,
+    /// Locals deeper than this are discarded when jumping out of the body
+    scope_depth: usize,
+    /// Jumps emitted by `break`, patched once the end of the loop is known
+    break_jumps: Vec<usize>,
+}
+
 #[derive(Ord, PartialOrd, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 enum Precedence {
     PrecNone = 0,
     PrecAssignment = 1,
@@ -59,19 +107,51 @@ struct ParseRule<'a> {
     precedence: Precedence,
 }
 
-type ParseFn<'a> = fn(&mut Parser<'a>) -> ();
+type ParseFn<'a> = fn(&mut Parser<'a>, bool) -> ();
+
+impl<'a> Compiler<'a> {
+    fn new(function_type: FunctionType, name: &str) -> Self {
+        let mut locals = Vec::new();
+        if function_type == FunctionType::Function {
+            // Slot 0 of a call frame holds the function being called
+            locals.push(Local { name: PLACEHOLDER_TOKEN, depth: Some(0), is_captured: false });
+        }
+
+        Self {
+            function: Function {
+                name: name.to_string(),
+                arity: 0,
+                upvalue_count: 0,
+                chunk: Chunk::new(),
+            },
+            function_type,
+            locals,
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        }
+    }
+
+    /// Counts the locals declared deeper than `depth`, which are always the last ones
+    fn count_locals_above(&self, depth: usize) -> usize {
+        self.locals.iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|local_depth| local_depth > depth))
+            .count()
+    }
+}
 
 impl<'a> Parser<'a> {
-    fn init(source: &'a str, chunk: &'a mut Chunk, repl: bool) -> Parser<'a> {
+    fn init(source: &'a str, repl: bool) -> Parser<'a> {
         let mut parser = Parser {
             scanner: Scanner::new(source),
-            chunk,
             repl,
             current: PLACEHOLDER_TOKEN,
             previous: PLACEHOLDER_TOKEN,
-            scope_depth: 0,
-            had_error: false,
+            compilers: vec![Compiler::new(FunctionType::Script, "script")],
+            diagnostics: Vec::new(),
             panic_mode: false,
+            open_blocks: Vec::new(),
             rules: Vec::new(),
         };
 
@@ -97,42 +177,48 @@ impl<'a> Parser<'a> {
 
                 #[rustfmt::skip]
                 let rule = match token_type {
-                    TokenLeftParen =>    rule(Some(Self::grouping), None,               PrecNone),
-                    TokenRightParen =>   rule(None,                 None,               PrecNone),
-                    TokenLeftBrace =>    rule(None,                 None,               PrecNone),
-                    TokenRightBrace =>   rule(None,                 None,               PrecNone),
-                    TokenComma =>        rule(None,                 None,               PrecNone),
-                    TokenDot =>          rule(None,                 None,               PrecNone),
-                    TokenMinus =>        rule(Some(Self::unary),    Some(Self::binary), PrecTerm),
-                    TokenPlus =>         rule(None,                 Some(Self::binary), PrecTerm),
-                    TokenSemicolon =>    rule(None,                 None,               PrecNone),
-                    TokenSlash =>        rule(None,                 Some(Self::binary), PrecFactor),
-                    TokenAsterisk =>     rule(None,                 Some(Self::binary), PrecFactor),
-                    TokenBang =>         rule(Some(Self::unary),    None,               PrecNone),
-                    TokenBangEqual =>    rule(None,                 Some(Self::binary), PrecEquality),
-                    TokenEqual =>        rule(None,                 None,               PrecNone),
-                    TokenEqualEqual =>   rule(None,                 Some(Self::binary), PrecEquality),
-                    TokenLess =>         rule(None,                 Some(Self::binary), PrecComparison),
-                    TokenLessEqual =>    rule(None,                 Some(Self::binary), PrecComparison),
-                    TokenGreater =>      rule(None,                 Some(Self::binary), PrecComparison),
-                    TokenGreaterEqual => rule(None,                 Some(Self::binary), PrecComparison),
-                    TokenAmpAmp =>       rule(None,                 None,               PrecNone),
-                    TokenPipePipe =>     rule(None,                 None,               PrecNone),
-                    TokenIdentifier =>   rule(None,                 None,               PrecNone),
-                    TokenString =>       rule(Some(Self::string),   None,               PrecNone),
-                    TokenNumber =>       rule(Some(Self::number),   None,               PrecNone),
-                    TokenElse =>         rule(None,                 None,               PrecNone),
-                    TokenFalse =>        rule(Some(Self::literal),  None,               PrecNone),
-                    TokenFun =>          rule(None,                 None,               PrecNone),
-                    TokenLet =>          rule(None,                 None,               PrecNone),
-                    TokenNil =>          rule(Some(Self::literal),  None,               PrecNone),
-                    TokenIf =>           rule(None,                 None,               PrecNone),
-                    TokenRepeat =>       rule(None,                 None,               PrecNone),
-                    TokenReturn =>       rule(None,                 None,               PrecNone),
-                    TokenTrue =>         rule(Some(Self::literal),  None,               PrecNone),
-                    TokenWhile =>        rule(None,                 None,               PrecNone),
-                    EOF =>               rule(None,                 None,               PrecNone),
-                    ScannerError =>      rule(None,                 None,               PrecNone),
+                    TokenLeftParen =>     rule(Some(Self::grouping), Some(Self::call),   PrecCall),
+                    TokenRightParen =>    rule(None,                 None,               PrecNone),
+                    TokenLeftBrace =>     rule(None,                 None,               PrecNone),
+                    TokenRightBrace =>    rule(None,                 None,               PrecNone),
+                    TokenComma =>         rule(None,                 None,               PrecNone),
+                    TokenDot =>           rule(None,                 None,               PrecNone),
+                    TokenMinus =>         rule(Some(Self::unary),    Some(Self::binary), PrecTerm),
+                    TokenPlus =>          rule(None,                 Some(Self::binary), PrecTerm),
+                    TokenSemicolon =>     rule(None,                 None,               PrecNone),
+                    TokenSlash =>         rule(None,                 Some(Self::binary), PrecFactor),
+                    TokenAsterisk =>      rule(None,                 Some(Self::binary), PrecFactor),
+                    TokenBang =>          rule(Some(Self::unary),    None,               PrecNone),
+                    TokenBangEqual =>     rule(None,                 Some(Self::binary), PrecEquality),
+                    TokenEqual =>         rule(None,                 None,               PrecNone),
+                    TokenEqualEqual =>    rule(None,                 Some(Self::binary), PrecEquality),
+                    TokenLess =>          rule(None,                 Some(Self::binary), PrecComparison),
+                    TokenLessEqual =>     rule(None,                 Some(Self::binary), PrecComparison),
+                    TokenGreater =>       rule(None,                 Some(Self::binary), PrecComparison),
+                    TokenGreaterEqual =>  rule(None,                 Some(Self::binary), PrecComparison),
+                    TokenAmpAmp =>        rule(None,                 Some(Self::and),    PrecAnd),
+                    TokenPipePipe =>      rule(None,                 Some(Self::or),     PrecOr),
+                    TokenMinusEqual =>    rule(None,                 None,               PrecNone),
+                    TokenPlusEqual =>     rule(None,                 None,               PrecNone),
+                    TokenSlashEqual =>    rule(None,                 None,               PrecNone),
+                    TokenAsteriskEqual => rule(None,                 None,               PrecNone),
+                    TokenIdentifier =>    rule(Some(Self::variable), None,               PrecNone),
+                    TokenString =>        rule(Some(Self::string),   None,               PrecNone),
+                    TokenNumber =>        rule(Some(Self::number),   None,               PrecNone),
+                    TokenBreak =>         rule(None,                 None,               PrecNone),
+                    TokenContinue =>      rule(None,                 None,               PrecNone),
+                    TokenElse =>          rule(None,                 None,               PrecNone),
+                    TokenFalse =>         rule(Some(Self::literal),  None,               PrecNone),
+                    TokenFun =>           rule(None,                 None,               PrecNone),
+                    TokenLet =>           rule(None,                 None,               PrecNone),
+                    TokenNil =>           rule(Some(Self::literal),  None,               PrecNone),
+                    TokenIf =>            rule(None,                 None,               PrecNone),
+                    TokenRepeat =>        rule(None,                 None,               PrecNone),
+                    TokenReturn =>        rule(None,                 None,               PrecNone),
+                    TokenTrue =>          rule(Some(Self::literal),  None,               PrecNone),
+                    TokenWhile =>         rule(None,                 None,               PrecNone),
+                    EOF =>                rule(None,                 None,               PrecNone),
+                    ScannerError =>       rule(None,                 None,               PrecNone),
                 };
 
                 self.rules.push(rule);
@@ -144,22 +230,29 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_precedence(&mut self, precedence: Precedence) {
-        self.advance();
-        let rule = self.get_rule(self.previous.token_type).prefix;
-
-        let prefix_rule = match rule {
+        // A token that can't start an expression is left alone, as it may well close the enclosing block or call
+        let prefix_rule = match self.get_rule(self.current.token_type).prefix {
             None => {
-                self.error("Expected expression.");
+                self.error_at_current("Expected expression.");
                 return;
             }
             Some(prefix_rule) => prefix_rule,
         };
+        self.advance();
 
-        prefix_rule(self);
+        // Only an expression parsed at assignment precedence may be the target of an assignment
+        let can_assign = precedence <= PrecAssignment;
+        prefix_rule(self, can_assign);
         while precedence <= self.get_rule(self.current.token_type).precedence {
             self.advance();
             let infix_rule = self.get_rule(self.previous.token_type).infix;
-            infix_rule.expect("This should only be reachable for some infix rule")(self);
+            infix_rule.expect("This should only be reachable for some infix rule")(self, can_assign);
+        }
+
+        if can_assign && self.match_assignment().is_some() {
+            let diagnostic = Diagnostic::error("Invalid assignment target.", self.previous.span)
+                .with_help("only variables can be assigned to");
+            self.report(diagnostic);
         }
     }
 
@@ -175,6 +268,65 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// A semicolon missing at the end of a line is reported right after the previous token,
+    /// and parsing carries on as if it was there
+    fn consume_semicolon(&mut self, message: &str) {
+        if self.match_token(TokenSemicolon) {
+            return;
+        }
+
+        if !self.panic_mode && (self.current.line > self.previous.line || self.check(EOF)) {
+            let end = self.previous.span.end;
+            self.report(Diagnostic::error(message, Span::new(end, end)));
+            self.panic_mode = false;
+        } else {
+            self.error_at_current(message);
+        }
+    }
+
+    /// Consumes the `)` closing a parenthesised list. When it is missing, the tokens up to the matching `)` are
+    /// skipped so that whatever follows, such as the body of an `if`, is compiled and checked as usual.
+    fn consume_closing_paren(&mut self, message: &str) {
+        if self.match_token(TokenRightParen) || self.panic_mode {
+            return;
+        }
+        self.error_at_current(message);
+
+        let mut depth = 0;
+        loop {
+            match self.current.token_type {
+                TokenRightParen if depth == 0 => {
+                    self.advance();
+                    self.panic_mode = false;
+                    return;
+                }
+                TokenLeftBrace => {
+                    self.panic_mode = false;
+                    return;
+                }
+                TokenSemicolon | TokenRightBrace | EOF => return,
+                TokenLeftParen => depth += 1,
+                TokenRightParen => depth -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    /// Consumes an assignment operator, returning the binary operation of a compound assignment if any
+    fn match_assignment(&mut self) -> Option<Option<u8>> {
+        let operation = match self.current.token_type {
+            TokenEqual => None,
+            TokenPlusEqual => Some(OP_ADD),
+            TokenMinusEqual => Some(OP_SUBTRACT),
+            TokenAsteriskEqual => Some(OP_MULTIPLY),
+            TokenSlashEqual => Some(OP_DIVIDE),
+            _ => return None,
+        };
+        self.advance();
+        Some(operation)
+    }
+
     fn match_token(&mut self, token: TokenType) -> bool {
         if self.check(token) { 
             self.advance();
@@ -195,26 +347,96 @@ impl<'a> Parser<'a> {
             self.current = self.scanner.next();
             if self.current.token_type != ScannerError {
                 break;
-            } else {
-                self.error_at_current(self.current.string);
             }
+
+            // Scanner errors point at exactly the bad text, so they are reported even while recovering from another error
+            self.diagnostics.push(Diagnostic::error(self.current.string, self.current.span));
+            self.panic_mode = true;
         }
     }
 
-    fn _emit_bytes(&mut self, byte1: u8, byte2: u8) {
-        self.chunk.write(byte1, self.previous.line as u16);
-        self.chunk.write(byte2, self.previous.line as u16);
+    fn compiler(&mut self) -> &mut Compiler<'a> {
+        self.compilers.last_mut().expect("No function is being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.compiler().function.chunk
+    }
+
+    /// Position of the previous token, saturating for sources too large to describe
+    fn position(&self) -> Position {
+        let line = u32::try_from(self.previous.line).unwrap_or(u32::MAX);
+        let column = u32::try_from(self.previous.column).unwrap_or(u32::MAX);
+        Position::new(line, column)
+    }
+
+    fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
+        self.emit_byte(byte1);
+        self.emit_byte(byte2);
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        self.chunk.write(byte, self.previous.line as u16);
+        let position = self.position();
+        self.chunk().write(byte, position);
+    }
+
+    /// Emits the instruction with its operand, switching to the long variant for constant indices past 255
+    fn emit_indexed(&mut self, op: u8, index: usize) {
+        let position = self.position();
+        self.chunk().write_indexed(op, index, position);
+    }
+
+    fn emit_f64(&mut self, value: f64) {
+        let position = self.position();
+        self.chunk().write_f64(value, position);
     }
 
     fn emit_constant(&mut self, constant: Value) {
-        if let Err(string) = self.chunk.write_constant(constant, self.previous.line as u16) {
+        let position = self.position();
+        if let Err(string) = self.chunk().write_constant(constant, position) {
             self.error(&string)
         }
     }
+
+    fn emit_jump(&mut self, op: u8) -> usize {
+        let position = self.position();
+        self.chunk().write_jump(op, position)
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        if let Err(string) = self.chunk().patch_jump(offset) {
+            self.error(&string)
+        }
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let position = self.position();
+        if let Err(string) = self.chunk().write_loop(loop_start, position) {
+            self.error(&string)
+        }
+    }
+
+    /// Finishes the function currently being compiled and returns to the one enclosing it
+    fn end_compiler(&mut self) -> (Function, Vec<UpvalueRef>) {
+        // The top level may run off the end of its chunk, which returns nil
+        if self.compiler().function_type != FunctionType::Script {
+            self.emit_bytes(OP_NIL, OP_RETURN);
+        }
+
+        let mut compiler = self.compilers.pop().expect("No function is being compiled");
+        compiler.function.upvalue_count = compiler.upvalues.len() as u8;
+        (compiler.function, compiler.upvalues)
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        match self.chunk().add_constant(Value::from(name)) {
+            Ok(index) => index,
+            Err(string) => {
+                self.error(&string);
+                0
+            }
+        }
+    }
     
     fn error_at_current(&mut self, message: &str) {
         self.error_at(&self.current.clone(), message);
@@ -225,115 +447,480 @@ impl<'a> Parser<'a> {
     }
 
     fn error_at(&mut self, token: &Token, message: &str) {
+        self.report(Diagnostic::error(message, token.span));
+    }
+
+    /// Records the diagnostic unless we are still recovering from an earlier error
+    fn report(&mut self, diagnostic: Diagnostic) {
         if self.panic_mode {
             return;
         }
         self.panic_mode = true;
+        self.diagnostics.push(diagnostic);
+    }
+    
+    fn begin_scope(&mut self) {
+        self.compiler().scope_depth += 1;
+    }
 
-        eprint!("[Line {}] Error ", token.line);
+    fn end_scope(&mut self) {
+        let compiler = self.compiler();
+        compiler.scope_depth -= 1;
+        let count = compiler.count_locals_above(compiler.scope_depth);
+        self.emit_local_pops(count);
 
-        if token.token_type == EOF {
-            eprint!("at end of file:");
-        } else if token.token_type != ScannerError {
-            eprint!("at {}: ", token.string);
+        let locals = &mut self.compiler().locals;
+        locals.truncate(locals.len() - count);
+    }
+
+    /// Emits pops for the locals deeper than `depth` without forgetting them, for jumps out of a scope
+    fn discard_locals_above(&mut self, depth: usize) {
+        let count = self.compiler().count_locals_above(depth);
+        self.emit_local_pops(count);
+    }
+
+    /// Emits a pop for each of the last `count` locals, closing the ones captured by closures
+    fn emit_local_pops(&mut self, count: usize) {
+        let captured: Vec<bool> = self.compiler().locals.iter()
+            .rev()
+            .take(count)
+            .map(|local| local.is_captured)
+            .collect();
+
+        for is_captured in captured {
+            self.emit_byte(if is_captured { OP_CLOSE_UPVALUE } else { OP_POP });
+        }
+    }
+
+    fn declare_local(&mut self) {
+        let name = self.previous;
+        let compiler = self.compiler();
+        let duplicate = compiler.locals.iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= compiler.scope_depth))
+            .find(|local| local.name.string == name.string);
+
+        if let Some(duplicate) = duplicate {
+            let note = format!("'{}' was first declared on line {}", name.string, duplicate.name.line);
+            self.report(Diagnostic::error("Already a variable with this name in this scope.", name.span).with_note(note));
         }
 
-        eprintln!(" {}", message);
-        self.had_error = true;
+        self.add_local(name);
     }
-    
-    fn begin_scope(&mut self) {
-        self.scope_depth += 1;
+
+    /// Adds an uninitialized local, unless the function already has as many locals as a slot operand can address
+    fn add_local(&mut self, name: Token<'a>) {
+        if self.compiler().locals.len() > u8::MAX as usize {
+            self.error("Too many local variables in function.");
+            return;
+        }
+
+        self.compiler().locals.push(Local { name, depth: None, is_captured: false });
     }
 
-    fn end_scope(&mut self) {
-        self.scope_depth -= 1;
+    /// Declares the variable named by the previous token, returning its name constant if it is a global
+    fn declare_variable(&mut self) -> Option<usize> {
+        if self.compiler().scope_depth == 0 {
+            Some(self.identifier_constant(self.previous.string))
+        } else {
+            self.declare_local();
+            None
+        }
+    }
+
+    fn define_variable(&mut self, global: Option<usize>) {
+        match global {
+            Some(index) => self.emit_indexed(OP_DEFINE_GLOBAL, index),
+            None => self.mark_initialized(),
+        }
+    }
+
+    fn mark_initialized(&mut self) {
+        let compiler = self.compiler();
+        let scope_depth = compiler.scope_depth;
+        if let Some(local) = compiler.locals.last_mut() {
+            local.depth = Some(scope_depth);
+        }
+    }
+
+    /// Resolves a local of the function at `compiler_index` in [Parser::compilers]
+    fn resolve_local(&mut self, compiler_index: usize, name: &str) -> Option<u8> {
+        let (slot, initialized) = self.compilers[compiler_index].locals.iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name.string == name)
+            .map(|(slot, local)| (slot, local.depth.is_some()))?;
+
+        if !initialized {
+            self.error("Can't read local variable in its own initializer.");
+        }
+
+        Some(slot as u8)
+    }
+
+    /// Resolves a variable of an enclosing function as an upvalue of the function at `compiler_index`,
+    /// threading it through every function in between
+    fn resolve_upvalue(&mut self, compiler_index: usize, name: &str) -> Option<u8> {
+        let enclosing = compiler_index.checked_sub(1)?;
+
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.compilers[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(compiler_index, UpvalueRef { index: slot, is_local: true }));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(compiler_index, UpvalueRef { index, is_local: false }))
+    }
+
+    fn add_upvalue(&mut self, compiler_index: usize, upvalue: UpvalueRef) -> u8 {
+        let upvalues = &self.compilers[compiler_index].upvalues;
+        if let Some(existing) = upvalues.iter()
+            .position(|existing| existing.index == upvalue.index && existing.is_local == upvalue.is_local) {
+            return existing as u8;
+        }
+
+        if upvalues.len() >= u8::MAX as usize {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+
+        let upvalues = &mut self.compilers[compiler_index].upvalues;
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
     }
 }
 
 // Statements
 impl<'a> Parser<'a> {
     fn declaration(&mut self) {
-        self.statement();
-        if self.panic_mode { self.synchronise() }
+        let start = self.current.span.start;
+        if self.match_token(TokenLet) {
+            self.let_declaration();
+        } else if self.match_token(TokenFun) {
+            self.fun_declaration();
+        } else {
+            self.statement();
+        }
+        if self.panic_mode { self.synchronise(start) }
+    }
+
+    fn let_declaration(&mut self) {
+        self.consume(TokenIdentifier, "Expect variable name.");
+        let global = self.declare_variable();
+
+        self.consume(TokenEqual, "Expect '=' after variable name.");
+        self.expression();
+        self.consume_semicolon("Expect ';' after variable declaration.");
+        self.define_variable(global);
+    }
+
+    fn fun_declaration(&mut self) {
+        self.consume(TokenIdentifier, "Expect function name.");
+        let global = self.declare_variable();
+        // A local function may refer to itself before its body is done
+        if global.is_none() {
+            self.mark_initialized();
+        }
+
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    /// Compiles the parameters and body of a function, leaving the function on the stack
+    fn function(&mut self, function_type: FunctionType) {
+        // The script is compiled by the first compiler, so the new function is nested as deep as there are compilers
+        if self.compilers.len() > MAX_FUNCTION_DEPTH {
+            self.error(&format!("Can't nest functions more than {} deep.", MAX_FUNCTION_DEPTH));
+        }
+
+        let name = self.previous.string;
+        self.compilers.push(Compiler::new(function_type, name));
+        self.begin_scope();
+
+        self.consume(TokenLeftParen, "Expect '(' after function name.");
+        if !self.check(TokenRightParen) {
+            loop {
+                if self.compiler().function.arity == u8::MAX {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                } else {
+                    self.compiler().function.arity += 1;
+                }
+
+                self.consume(TokenIdentifier, "Expect parameter name.");
+                self.declare_local();
+                self.mark_initialized();
+
+                if !self.match_token(TokenComma) {
+                    break;
+                }
+            }
+        }
+        self.consume_closing_paren("Expect ')' after parameters.");
+        self.consume(TokenLeftBrace, "Expect '{' before function body.");
+        self.block();
+
+        let (function, upvalues) = self.end_compiler();
+        let index = match self.chunk().add_constant(Value::Obj(Obj::Function(Rc::new(function)))) {
+            Ok(index) => index,
+            Err(string) => {
+                self.error(&string);
+                0
+            }
+        };
+
+        self.emit_indexed(OP_CLOSURE, index);
+        for upvalue in upvalues {
+            self.emit_bytes(upvalue.is_local as u8, upvalue.index);
+        }
     }
     
     fn statement(&mut self) {
-        if self.match_token(TokenLeftBrace) {
+        if self.match_token(TokenIf) {
+            self.if_statement();
+        } else if self.match_token(TokenWhile) {
+            self.while_statement();
+        } else if self.match_token(TokenRepeat) {
+            self.repeat_statement();
+        } else if self.match_token(TokenBreak) {
+            self.break_statement();
+        } else if self.match_token(TokenContinue) {
+            self.continue_statement();
+        } else if self.match_token(TokenReturn) {
+            self.return_statement();
+        } else if self.match_token(TokenLeftBrace) {
             self.begin_scope();
             self.block();
             self.end_scope();
+        } else if self.match_token(TokenElse) {
+            self.error("'else' without a matching 'if'.");
+            // The 'else' is the only problem, so its body is still checked
+            self.panic_mode = false;
+            self.statement();
+        } else if self.open_blocks.is_empty() && self.check(TokenRightBrace) {
+            self.error_at_current("Unmatched '}'.");
+            self.advance();
         } else {
             self.expression_statement()
         }
     }
     
+    fn if_statement(&mut self) {
+        self.consume(TokenLeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume_closing_paren("Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        self.emit_byte(OP_POP);
+        self.statement();
+        let else_jump = self.emit_jump(OP_JUMP);
+
+        self.patch_jump(then_jump);
+        self.emit_byte(OP_POP);
+        if self.match_token(TokenElse) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.chunk().code.len();
+        self.consume(TokenLeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume_closing_paren("Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        self.emit_byte(OP_POP);
+        self.loop_body(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OP_POP);
+        self.end_loop();
+    }
+
+    /// `repeat (n)` runs the body while a hidden counter starting at `n` is positive,
+    /// while a bare `repeat` loops until it is broken out of
+    fn repeat_statement(&mut self) {
+        if !self.match_token(TokenLeftParen) {
+            let loop_start = self.chunk().code.len();
+            self.loop_body(loop_start);
+            self.end_loop();
+            return;
+        }
+
+        // The counter is named after the keyword so that no identifier can refer to it
+        let keyword = self.previous;
+        self.begin_scope();
+        self.expression();
+        self.consume_closing_paren("Expect ')' after repeat count.");
+        self.add_local(keyword);
+        self.mark_initialized();
+        let counter = (self.compiler().locals.len() - 1) as u8;
+
+        let loop_start = self.chunk().code.len();
+        self.emit_bytes(OP_GET_LOCAL, counter);
+        self.emit_f64(0.0);
+        self.emit_byte(OP_GREATER_THAN);
+        let exit_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        self.emit_byte(OP_POP);
+
+        self.emit_bytes(OP_GET_LOCAL, counter);
+        self.emit_f64(1.0);
+        self.emit_byte(OP_SUBTRACT);
+        self.emit_bytes(OP_SET_LOCAL, counter);
+        self.emit_byte(OP_POP);
+        self.loop_body(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OP_POP);
+        self.end_loop();
+        self.end_scope();
+    }
+
+    /// Compiles the body of a loop followed by the jump back to `loop_start`.
+    /// Must be followed by [Parser::end_loop] once the exit of the loop has been emitted.
+    fn loop_body(&mut self, loop_start: usize) {
+        let compiler = self.compiler();
+        compiler.loops.push(Loop {
+            start: loop_start,
+            scope_depth: compiler.scope_depth,
+            break_jumps: Vec::new(),
+        });
+        self.statement();
+        self.emit_loop(loop_start);
+    }
+
+    fn end_loop(&mut self) {
+        let current_loop = self.compiler().loops.pop().expect("Not in a loop");
+        for jump in current_loop.break_jumps {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn break_statement(&mut self) {
+        match self.compiler().loops.last().map(|current_loop| current_loop.scope_depth) {
+            None => self.error("Can't use 'break' outside of a loop."),
+            Some(scope_depth) => {
+                self.discard_locals_above(scope_depth);
+                let jump = self.emit_jump(OP_JUMP);
+                self.compiler().loops.last_mut().unwrap().break_jumps.push(jump);
+            }
+        }
+        self.consume_semicolon("Expect ';' after 'break'.");
+    }
+
+    fn continue_statement(&mut self) {
+        match self.compiler().loops.last().map(|current_loop| (current_loop.start, current_loop.scope_depth)) {
+            None => self.error("Can't use 'continue' outside of a loop."),
+            Some((start, scope_depth)) => {
+                self.discard_locals_above(scope_depth);
+                self.emit_loop(start);
+            }
+        }
+        self.consume_semicolon("Expect ';' after 'continue'.");
+    }
+
+    fn return_statement(&mut self) {
+        if self.compiler().function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.match_token(TokenSemicolon) {
+            self.emit_bytes(OP_NIL, OP_RETURN);
+        } else {
+            self.expression();
+            self.consume_semicolon("Expect ';' after return value.");
+            self.emit_byte(OP_RETURN);
+        }
+    }
+
+    /// Compiles the statements of a block whose `{` is the previous token
     fn block(&mut self) {
+        let opening = self.previous;
+        self.open_blocks.push(opening);
         while !self.check(TokenRightBrace) && !self.check(EOF) {
             self.declaration()
         }
-        
-        self.consume(TokenRightBrace, "Expect '}' after block.");
+        self.open_blocks.pop();
+
+        if !self.match_token(TokenRightBrace) {
+            let note = format!("the block was opened on line {}", opening.line);
+            self.report(Diagnostic::error("Expect '}' after block.", self.current.span).with_note(note));
+        }
     }
     
     fn expression_statement(&mut self) {
         self.expression();
-        if self.check(TokenSemicolon) {
-            self.advance();
-            self.emit_byte(OP_POP);
-        } else if self.repl && self.check(EOF) {
+        if self.repl && self.check(EOF) {
             self.emit_byte(OP_RETURN);
+        } else if self.check(TokenRightParen) {
+            self.error_at_current("Unmatched ')'.");
         } else {
-            self.error_at_current("Expect ';' after expression.");
+            self.consume_semicolon("Expect ';' after expression.");
+            self.emit_byte(OP_POP);
         }
     }
     
-    fn synchronise(&mut self) {
+    /// Skips tokens until the start of the next statement, stepping over whole blocks.
+    /// A `}` closing the enclosing block is left for that block to consume.
+    /// `start` is the offset of the failed statement, which is always skipped past so that parsing makes progress.
+    fn synchronise(&mut self, start: usize) {
         self.panic_mode = false;
+        let mut depth = 0;
 
         while !self.check(EOF) {
-            if self.previous.token_type == TokenSemicolon {
+            if depth == 0 && self.previous.token_type == TokenSemicolon && self.current.span.start != start {
                 return;
             }
-            
-            match self.current.token_type { 
-                TokenFun | TokenLet | TokenRepeat | TokenIf | TokenWhile | TokenReturn => {
+
+            match self.current.token_type {
+                TokenFun | TokenLet | TokenRepeat | TokenIf | TokenWhile | TokenReturn | TokenBreak | TokenContinue if depth == 0 => {
                     return
                 }
-                _ => self.advance()
+                TokenRightBrace if depth == 0 && !self.open_blocks.is_empty() => return,
+                TokenLeftBrace => depth += 1,
+                TokenRightBrace if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => {}
             }
+            self.advance();
         }
-        
-        self.advance()
     }
 }
 
 /// Prefix parsing
 impl<'a> Parser<'a> {
-    fn grouping(&mut self) {
+    fn grouping(&mut self, _can_assign: bool) {
         self.expression();
-        self.consume(TokenRightParen, "Expected ')' after expression.");
+        self.consume_closing_paren("Expected ')' after expression.");
     }
 
-    fn number(&mut self) {
+    fn number(&mut self, _can_assign: bool) {
         match self.previous.string.parse::<f64>() {
-            Ok(value) => self.chunk.write_f64(value, self.previous.line as u16),
+            Ok(value) => self.emit_f64(value),
             Err(_) => self.error("Failed to parse number."),
         }
     }
 
-    fn unary(&mut self) {
+    fn unary(&mut self, _can_assign: bool) {
         let operator_type = self.previous.token_type;
-        self.expression();
+        let position = self.position();
+        self.parse_precedence(PrecUnary);
 
-        match operator_type {
-            TokenMinus => self.emit_byte(OP_NEGATE),
-            TokenBang => self.emit_byte(OP_NOT),
+        let op = match operator_type {
+            TokenMinus => OP_NEGATE,
+            TokenBang => OP_NOT,
             _ => unreachable!(),
-        }
+        };
+        // Runtime errors point at the operator rather than its operand
+        self.chunk().write(op, position);
     }
     
-    fn literal(&mut self) {
+    fn literal(&mut self, _can_assign: bool) {
         match self.previous.token_type {
             TokenNil => self.emit_byte(OP_NIL),
             TokenTrue => self.emit_byte(OP_TRUE),
@@ -342,33 +929,102 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn string(&mut self) {
-        let token_slice = self.previous.string;
-        let string_copy = self.previous.string[1..(token_slice.len() - 1)].to_string();
-        self.emit_constant(Value::from(string_copy));
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.previous.string;
+        let current = self.compilers.len() - 1;
+        let (get_op, set_op, operand) = if let Some(slot) = self.resolve_local(current, name) {
+            (OP_GET_LOCAL, OP_SET_LOCAL, slot as usize)
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            (OP_GET_UPVALUE, OP_SET_UPVALUE, index as usize)
+        } else {
+            (OP_GET_GLOBAL, OP_SET_GLOBAL, self.identifier_constant(name))
+        };
+
+        let assignment = if can_assign { self.match_assignment() } else { None };
+        match assignment {
+            Some(None) => {
+                self.expression();
+                self.emit_indexed(set_op, operand);
+            }
+            Some(Some(operation)) => {
+                self.emit_indexed(get_op, operand);
+                self.expression();
+                self.emit_byte(operation);
+                self.emit_indexed(set_op, operand);
+            }
+            None => self.emit_indexed(get_op, operand),
+        }
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        let value = scanner::string_value(self.previous.string);
+        self.emit_constant(Value::from(value));
     }
 }
 
 /// Infix parsing
 impl<'a> Parser<'a> {
-    fn binary(&mut self) {
+    fn binary(&mut self, _can_assign: bool) {
         let operator_type = self.previous.token_type;
+        let position = self.position();
         let rule = self.get_rule(operator_type);
         self.parse_precedence(rule.precedence.next());
 
-        match operator_type {
-            TokenPlus => self.emit_byte(OP_ADD),
-            TokenMinus => self.emit_byte(OP_SUBTRACT),
-            TokenAsterisk => self.emit_byte(OP_MULTIPLY),
-            TokenSlash => self.emit_byte(OP_DIVIDE),
-            TokenEqualEqual => self.emit_byte(OP_EQUALS),
-            TokenBangEqual => self.emit_byte(OP_NOT_EQUALS),
-            TokenLess => self.emit_byte(OP_LESS_THAN),
-            TokenLessEqual => self.emit_byte(OP_LESS_THAN_OR_EQUALS),
-            TokenGreater => self.emit_byte(OP_GREATER_THAN),
-            TokenGreaterEqual => self.emit_byte(OP_GREATER_THAN_OR_EQUALS),
+        let op = match operator_type {
+            TokenPlus => OP_ADD,
+            TokenMinus => OP_SUBTRACT,
+            TokenAsterisk => OP_MULTIPLY,
+            TokenSlash => OP_DIVIDE,
+            TokenEqualEqual => OP_EQUALS,
+            TokenBangEqual => OP_NOT_EQUALS,
+            TokenLess => OP_LESS_THAN,
+            TokenLessEqual => OP_LESS_THAN_OR_EQUALS,
+            TokenGreater => OP_GREATER_THAN,
+            TokenGreaterEqual => OP_GREATER_THAN_OR_EQUALS,
             _ => unreachable!(),
+        };
+        self.chunk().write(op, position);
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let arg_count = self.argument_list();
+        self.emit_bytes(OP_CALL, arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let mut arg_count: u8 = 0;
+        if !self.check(TokenRightParen) {
+            loop {
+                self.expression();
+                if arg_count == u8::MAX {
+                    self.error("Can't have more than 255 arguments.");
+                } else {
+                    arg_count += 1;
+                }
+
+                if !self.match_token(TokenComma) {
+                    break;
+                }
+            }
         }
+        self.consume_closing_paren("Expect ')' after arguments.");
+        arg_count
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        self.emit_byte(OP_POP);
+        self.parse_precedence(PrecAnd);
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        let end_jump = self.emit_jump(OP_JUMP);
+        self.patch_jump(else_jump);
+        self.emit_byte(OP_POP);
+        self.parse_precedence(PrecOr);
+        self.patch_jump(end_jump);
     }
 }
 
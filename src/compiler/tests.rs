@@ -1,16 +1,26 @@
 mod bools;
+mod constants;
+mod control_flow;
+mod diagnostics;
+mod functions;
+mod limits;
 mod numbers;
+mod recovery;
 mod statements;
 
 use crate::bytecode::codes::*;
 use std::collections::VecDeque;
 
 fn compile(source: &str) -> VecDeque<u8> {
-    super::compile(source.to_string(), true).unwrap().code.into()
+    super::compile(source, true).unwrap().code.into()
 }
 
 fn repl_compile(source: &str) -> VecDeque<u8> {
-    super::compile(source.to_string(), true).unwrap().code.into()
+    super::compile(source, true).unwrap().code.into()
+}
+
+fn assert_compile_error(source: &str) {
+    assert!(super::compile(source, true).is_err(), "Expected compile error for {:?}", source);
 }
 
 fn match_byte(code: &mut VecDeque<u8>, byte: u8) {
@@ -17,3 +17,32 @@ binary_operation_test!(less_than, "<", OP_LESS_THAN);
 binary_operation_test!(less_than_or_equals, "<=", OP_LESS_THAN_OR_EQUALS);
 binary_operation_test!(greater_than, ">", OP_GREATER_THAN);
 binary_operation_test!(greater_than_or_equals, ">=", OP_GREATER_THAN_OR_EQUALS);
+
+#[test]
+fn and_operator() {
+    let mut code = repl_compile("true && false");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 2);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_FALSE);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
+
+#[test]
+fn or_operator() {
+    let mut code = repl_compile("true || false");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 3);
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 2);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_FALSE);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
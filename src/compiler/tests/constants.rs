@@ -0,0 +1,91 @@
+use crate::bytecode::codes::*;
+use crate::compiler::tests::*;
+use crate::vm::value::Value;
+
+#[test]
+fn repeated_constants_share_a_slot() {
+    let mut code = compile("\"a\"; \"b\"; \"a\"; a; a = \"b\";");
+    match_byte(&mut code, OP_CONTANT);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_CONTANT);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_CONTANT);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_GET_GLOBAL);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_CONTANT);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, OP_SET_GLOBAL);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn long_constants() {
+    let source: String = (0..300).map(|i| format!("\"{}\";", i)).collect();
+    let chunk = crate::compiler::compile(&source, true).unwrap();
+    assert_eq!(chunk.constants().len(), 300);
+    assert_eq!(chunk.load_constant(299), Value::from("299"));
+
+    let mut code: std::collections::VecDeque<u8> = chunk.code.into();
+    for i in 0..256 {
+        match_byte(&mut code, OP_CONTANT);
+        match_byte(&mut code, i as u8);
+        match_byte(&mut code, OP_POP);
+    }
+    for i in 256..300u32 {
+        let [_, high, middle, low] = i.to_be_bytes();
+        match_byte(&mut code, OP_CONSTANT_LONG);
+        match_byte(&mut code, high);
+        match_byte(&mut code, middle);
+        match_byte(&mut code, low);
+        match_byte(&mut code, OP_POP);
+    }
+    assert_empty(&code);
+}
+
+/// Compiles the source after 300 string constants, skipping over the code that loads them
+fn compile_after_300_constants(source: &str) -> std::collections::VecDeque<u8> {
+    let prefix: String = (0..300).map(|i| format!("\"{}\";", i)).collect();
+    let mut code = compile(&(prefix + source));
+    code.drain(..256 * 3 + 44 * 5);
+    code
+}
+
+fn match_long_index(code: &mut std::collections::VecDeque<u8>, index: u32) {
+    let [_, high, middle, low] = index.to_be_bytes();
+    match_byte(code, high);
+    match_byte(code, middle);
+    match_byte(code, low);
+}
+
+#[test]
+fn long_global_operands() {
+    let mut code = compile_after_300_constants("let x = 1; x = 2; x;");
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_DEFINE_GLOBAL_LONG);
+    match_long_index(&mut code, 300);
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_SET_GLOBAL_LONG);
+    match_long_index(&mut code, 300);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_GET_GLOBAL_LONG);
+    match_long_index(&mut code, 300);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn long_closure_operands() {
+    let mut code = compile_after_300_constants("fun f() { return 1; }");
+    match_byte(&mut code, OP_CLOSURE_LONG);
+    match_long_index(&mut code, 301);
+    match_byte(&mut code, OP_DEFINE_GLOBAL_LONG);
+    match_long_index(&mut code, 300);
+    assert_empty(&code);
+}
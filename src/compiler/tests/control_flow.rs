@@ -0,0 +1,174 @@
+use crate::bytecode::codes::*;
+use crate::compiler::tests::*;
+
+#[test]
+fn if_statement() {
+    let mut code = compile("if (true) 1;");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 14);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn if_else_statement() {
+    let mut code = compile("if (true) 1; else 2;");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 14);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 11);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn else_if_chain() {
+    let mut code = compile("if (true) 1; else if (false) 2; else 3;");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 14);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 30);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_FALSE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 14);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 11);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 3.0);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn while_statement() {
+    let mut code = compile("while (false) 1;");
+    match_byte(&mut code, OP_FALSE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 14);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_LOOP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 18);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn invalid_control_flow() {
+    assert_compile_error("if true 1;");
+    assert_compile_error("if (true 1;");
+    assert_compile_error("while true 1;");
+    assert_compile_error("else 1;");
+}
+
+#[test]
+fn infinite_repeat() {
+    let mut code = compile("repeat break;");
+    match_byte(&mut code, OP_JUMP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 3);
+    match_byte(&mut code, OP_LOOP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 6);
+    assert_empty(&code);
+}
+
+#[test]
+fn counted_repeat() {
+    let mut code = compile("repeat (2) 1;");
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_GET_LOCAL);
+    match_byte(&mut code, 0);
+    match_f64_op(&mut code, 0.0);
+    match_byte(&mut code, OP_GREATER_THAN);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 29);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_GET_LOCAL);
+    match_byte(&mut code, 0);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_SUBTRACT);
+    match_byte(&mut code, OP_SET_LOCAL);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_LOOP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 44);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn continue_discards_locals() {
+    let mut code = compile("while (true) { let a = 1; continue; }");
+    match_byte(&mut code, OP_TRUE);
+    match_byte(&mut code, OP_JUMP_IF_FALSE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 18);
+    match_byte(&mut code, OP_POP);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_LOOP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 18);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_LOOP);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 22);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn break_outside_loop() {
+    assert_compile_error("break;");
+    assert_compile_error("continue;");
+    assert_compile_error("{ break; }");
+    assert_compile_error("repeat (1 break;");
+}
+
+#[test]
+fn repeat_counter_counts_towards_local_limit() {
+    let locals: String = (0..256).map(|i| format!("let v{} = 0; ", i)).collect();
+    assert!(crate::compiler::compile(&format!("{{ {} }}", locals), true).is_ok());
+
+    let Err(diagnostics) = crate::compiler::compile(&format!("{{ {} repeat (1) {{}} }}", locals), true) else {
+        panic!("Expected the repeat counter to exceed the local limit");
+    };
+    assert_eq!(diagnostics[0].message, "Too many local variables in function.");
+}
@@ -0,0 +1,65 @@
+use crate::diagnostic::{Diagnostic, Severity, Span};
+
+fn diagnostics(source: &str) -> Vec<Diagnostic> {
+    crate::compiler::compile(source, false).expect_err("Expected compile errors")
+}
+
+#[test]
+fn every_statement_reports_its_error() {
+    let source = "let = 1;\nprint(1 +);\nlet b = 2";
+    let diagnostics = diagnostics(source);
+    let reported: Vec<(&str, Span)> = diagnostics.iter()
+        .map(|diagnostic| (diagnostic.message.as_str(), diagnostic.span))
+        .collect();
+
+    assert_eq!(reported, vec![
+        ("Expect variable name.", Span::new(4, 5)),
+        ("Expected expression.", Span::new(18, 19)),
+        ("Expect ';' after variable declaration.", Span::new(30, 30)),
+    ]);
+    assert!(diagnostics.iter().all(|diagnostic| diagnostic.severity == Severity::Error));
+}
+
+#[test]
+fn scanner_errors_point_at_the_offending_text() {
+    let diagnostics = diagnostics("let a = 1 | 2;");
+    assert_eq!(diagnostics[0].message, "Unexpected |");
+    assert_eq!(diagnostics[0].span, Span::new(10, 11));
+}
+
+#[test]
+fn duplicate_local_notes_the_first_declaration() {
+    let source = "{\n  let a = 1;\n  let a = 2;\n}";
+    let diagnostics = diagnostics(source);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].render(source), "\
+error: Already a variable with this name in this scope.
+ --> 3:7
+  |
+3 |   let a = 2;
+  |       ^
+  = note: 'a' was first declared on line 2
+");
+}
+
+#[test]
+fn invalid_assignment_target_has_help() {
+    let diagnostics = diagnostics("let a = 1; a + 1 = 2;");
+    assert_eq!(diagnostics[0].message, "Invalid assignment target.");
+    assert_eq!(diagnostics[0].span, Span::new(17, 18));
+    assert_eq!(diagnostics[0].help.as_deref(), Some("only variables can be assigned to"));
+}
+
+#[test]
+fn invalid_escape_points_at_the_escape() {
+    let source = "let s = \"ok\";\nlet t = \"bad \\u{110000} \\q\";";
+    let diagnostics = diagnostics(source);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].render(source), "\
+error: Invalid unicode code point
+ --> 2:14
+  |
+2 | let t = \"bad \\u{110000} \\q\";
+  |              ^^^^^^^^^^
+");
+}
@@ -0,0 +1,165 @@
+use crate::bytecode::codes::*;
+use crate::compiler::tests::*;
+use crate::vm::value::{Obj, Value};
+use std::collections::VecDeque;
+
+fn function_constant(source: &str, index: usize) -> (String, u8, VecDeque<u8>) {
+    let chunk = crate::compiler::compile(source, true).unwrap();
+    match chunk.load_constant(index) {
+        Value::Obj(Obj::Function(function)) => (function.name.clone(), function.arity, function.chunk.code.clone().into()),
+        other => panic!("Expected function constant, got {}", other),
+    }
+}
+
+#[test]
+fn function_declaration() {
+    let mut code = compile("fun f(a) { return a; }");
+    match_byte(&mut code, OP_CLOSURE);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, OP_DEFINE_GLOBAL);
+    match_byte(&mut code, 0);
+    assert_empty(&code);
+
+    let (name, arity, mut code) = function_constant("fun f(a) { return a; }", 1);
+    assert_eq!(name, "f");
+    assert_eq!(arity, 1);
+    match_byte(&mut code, OP_GET_LOCAL);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, OP_RETURN);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
+
+#[test]
+fn implicit_return() {
+    let (_, arity, mut code) = function_constant("fun f() { 1; }", 1);
+    assert_eq!(arity, 0);
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+
+    let (_, _, mut code) = function_constant("fun f() { return; }", 1);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
+
+#[test]
+fn call() {
+    let mut code = compile("f(1, 2);");
+    match_byte(&mut code, OP_GET_GLOBAL);
+    match_byte(&mut code, 0);
+    match_f64_op(&mut code, 1.0);
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_CALL);
+    match_byte(&mut code, 2);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn local_function() {
+    let mut code = compile("{ fun f() {} f(); }");
+    match_byte(&mut code, OP_CLOSURE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_GET_LOCAL);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_CALL);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_POP);
+    assert_empty(&code);
+}
+
+#[test]
+fn invalid_functions() {
+    assert_compile_error("return 1;");
+    assert_compile_error("fun f(a, a) {}");
+    assert_compile_error("fun f(a b) {}");
+    assert_compile_error("fun f() return 1;");
+    assert_compile_error("fun () {}");
+    assert_compile_error("f(1, 2;");
+    assert_compile_error("while (true) { fun f() { break; } }");
+}
+
+#[test]
+fn captured_local() {
+    let mut code = compile("{ let a = 1; fun f() { return a; } }");
+    match_f64_op(&mut code, 1.0);
+    match_byte(&mut code, OP_CLOSURE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 1);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_CLOSE_UPVALUE);
+    assert_empty(&code);
+
+    let (_, _, mut code) = function_constant("{ let a = 1; fun f() { return a; } }", 0);
+    match_byte(&mut code, OP_GET_UPVALUE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_RETURN);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
+
+#[test]
+fn transitive_upvalue() {
+    let chunk = crate::compiler::compile("fun outer() { let a = 1; fun middle() { fun inner() { a = 2; } } }", true).unwrap();
+    let outer = match chunk.load_constant(1) {
+        Value::Obj(Obj::Function(function)) => function,
+        other => panic!("Expected function constant, got {}", other),
+    };
+    let middle = match outer.chunk.load_constant(0) {
+        Value::Obj(Obj::Function(function)) => function,
+        other => panic!("Expected function constant, got {}", other),
+    };
+    assert_eq!(middle.upvalue_count, 1);
+
+    let mut code: VecDeque<u8> = middle.chunk.code.clone().into();
+    match_byte(&mut code, OP_CLOSURE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+
+    let inner = match middle.chunk.load_constant(0) {
+        Value::Obj(Obj::Function(function)) => function,
+        other => panic!("Expected function constant, got {}", other),
+    };
+    let mut code: VecDeque<u8> = inner.chunk.code.clone().into();
+    match_f64_op(&mut code, 2.0);
+    match_byte(&mut code, OP_SET_UPVALUE);
+    match_byte(&mut code, 0);
+    match_byte(&mut code, OP_POP);
+    match_byte(&mut code, OP_NIL);
+    match_byte(&mut code, OP_RETURN);
+    assert_empty(&code);
+}
+
+#[test]
+fn upvalue_limit() {
+    // The innermost function captures 128 locals of `outer` through `middle`, and the rest from `middle` itself
+    let capture = |count: usize| {
+        let outer: String = (0..128).map(|i| format!("let a{} = 0; ", i)).collect();
+        let middle: String = (0..count - 128).map(|i| format!("let b{} = 0; ", i)).collect();
+        let uses: String = (0..128).map(|i| format!("a{}; ", i))
+            .chain((0..count - 128).map(|i| format!("b{}; ", i)))
+            .collect();
+        format!("fun outer() {{ {} fun middle() {{ {} fun inner() {{ {} }} }} }}", outer, middle, uses)
+    };
+
+    assert!(crate::compiler::compile(&capture(255), true).is_ok());
+
+    let Err(diagnostics) = crate::compiler::compile(&capture(256), true) else {
+        panic!("Expected the 256th upvalue to be rejected");
+    };
+    assert_eq!(diagnostics[0].message, "Too many closure variables in function.");
+}
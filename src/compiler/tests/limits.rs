@@ -0,0 +1,22 @@
+use crate::bytecode::chunk::MAX_FUNCTION_DEPTH;
+
+/// Source for functions nested `depth` deep
+fn nested_functions(depth: usize) -> String {
+    let mut source = String::new();
+    for level in 0..depth {
+        source.push_str(&format!("fun f{}() {{ ", level));
+    }
+    source + &"}".repeat(depth)
+}
+
+#[test]
+fn function_nesting_limit() {
+    let chunk = crate::compiler::compile(&nested_functions(MAX_FUNCTION_DEPTH), true).unwrap();
+    assert!(crate::bytecode::verifier::verify(&chunk).is_ok());
+
+    let Err(diagnostics) = crate::compiler::compile(&nested_functions(MAX_FUNCTION_DEPTH + 1), true) else {
+        panic!("Expected functions nested too deep to be rejected");
+    };
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Can't nest functions more than 256 deep.");
+}
@@ -0,0 +1,31 @@
+//! Compiles every broken program in `corpus/` and compares the diagnostics with the `.expected` file next to it.
+//! Run with `FOPS_BLESS` set to rewrite the expected files after an intended change.
+
+use crate::diagnostic::render_all;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn corpus() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/compiler/tests/corpus");
+    let mut programs: Vec<_> = fs::read_dir(&directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "fops"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "No programs found in {}", directory.display());
+
+    for path in programs {
+        let source = fs::read_to_string(&path).unwrap();
+        let diagnostics = crate::compiler::compile(&source, false).expect_err("Expected compile errors");
+        let actual = render_all(&diagnostics, &source);
+        if std::env::var_os("FOPS_BLESS").is_some() {
+            fs::write(path.with_extension("expected"), &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(path.with_extension("expected"))
+            .unwrap_or_else(|_| panic!("Missing expected output for {}", path.display()));
+        assert_eq!(actual, expected, "Diagnostics for {} changed", path.display());
+    }
+}
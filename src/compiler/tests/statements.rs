@@ -1,4 +1,4 @@
-use crate::bytecode::codes::{OP_EQUALS, OP_FALSE, OP_POP};
+use crate::bytecode::codes::*;
 use crate::compiler::tests;
 
 #[test]
@@ -27,7 +27,7 @@ fn block_statements() {
 
 #[test]
 fn contiguous_locals() {
-    let mut code = tests::compile("let a = 1; let b = 2;");
+    let mut code = tests::compile("{ let a = 1; let b = 2; }");
     tests::match_f64_op(&mut code, 1.0);
     tests::match_f64_op(&mut code, 2.0);
     tests::match_byte(&mut code, OP_POP);
@@ -36,7 +36,7 @@ fn contiguous_locals() {
 
 #[test]
 fn contiguous_locals_with_block() {
-    let mut code = tests::compile("let a = 1; { let b = 2; } let c = 3;");
+    let mut code = tests::compile("{ let a = 1; { let b = 2; } let c = 3; }");
     tests::match_f64_op(&mut code, 1.0);
     tests::match_f64_op(&mut code, 2.0);
     tests::match_byte(&mut code, OP_POP);
@@ -44,3 +44,97 @@ fn contiguous_locals_with_block() {
     tests::match_byte(&mut code, OP_POP);
     tests::match_byte(&mut code, OP_POP);
 }
+
+#[test]
+fn local_get_and_set() {
+    let mut code = tests::compile("{ let a = 1; a = 2; a; }");
+    tests::match_f64_op(&mut code, 1.0);
+    tests::match_f64_op(&mut code, 2.0);
+    tests::match_byte(&mut code, OP_SET_LOCAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_GET_LOCAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_POP);
+    tests::assert_empty(&code);
+}
+
+#[test]
+fn shadowed_local() {
+    let mut code = tests::compile("{ let a = 1; { let a = 2; a; } }");
+    tests::match_f64_op(&mut code, 1.0);
+    tests::match_f64_op(&mut code, 2.0);
+    tests::match_byte(&mut code, OP_GET_LOCAL);
+    tests::match_byte(&mut code, 1);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_POP);
+    tests::assert_empty(&code);
+}
+
+#[test]
+fn invalid_locals() {
+    tests::assert_compile_error("{ let a = 1; let a = 2; }");
+    tests::assert_compile_error("{ let a = a; }");
+    tests::assert_compile_error("{ let a }");
+}
+
+#[test]
+fn globals() {
+    let mut code = tests::compile("let a = 1; a = 2; a;");
+    tests::match_f64_op(&mut code, 1.0);
+    tests::match_byte(&mut code, OP_DEFINE_GLOBAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_f64_op(&mut code, 2.0);
+    tests::match_byte(&mut code, OP_SET_GLOBAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_GET_GLOBAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_byte(&mut code, OP_POP);
+    tests::assert_empty(&code);
+}
+
+#[test]
+fn assignment_expression() {
+    let mut code = tests::compile("{ let a = 1; let b = 2; a = b = 3; }");
+    tests::match_f64_op(&mut code, 1.0);
+    tests::match_f64_op(&mut code, 2.0);
+    tests::match_f64_op(&mut code, 3.0);
+    tests::match_byte(&mut code, OP_SET_LOCAL);
+    tests::match_byte(&mut code, 1);
+    tests::match_byte(&mut code, OP_SET_LOCAL);
+    tests::match_byte(&mut code, 0);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_POP);
+    tests::match_byte(&mut code, OP_POP);
+    tests::assert_empty(&code);
+}
+
+#[test]
+fn compound_assignment() {
+    for (operator, opcode) in [("+=", OP_ADD), ("-=", OP_SUBTRACT), ("*=", OP_MULTIPLY), ("/=", OP_DIVIDE)] {
+        let mut code = tests::compile(format!("{{ let a = 1; a {} 2; }}", operator).as_str());
+        tests::match_f64_op(&mut code, 1.0);
+        tests::match_byte(&mut code, OP_GET_LOCAL);
+        tests::match_byte(&mut code, 0);
+        tests::match_f64_op(&mut code, 2.0);
+        tests::match_byte(&mut code, opcode);
+        tests::match_byte(&mut code, OP_SET_LOCAL);
+        tests::match_byte(&mut code, 0);
+        tests::match_byte(&mut code, OP_POP);
+        tests::match_byte(&mut code, OP_POP);
+        tests::assert_empty(&code);
+    }
+}
+
+#[test]
+fn invalid_assignment_targets() {
+    tests::assert_compile_error("let a = 1; let b = 2; a + b = 3;");
+    tests::assert_compile_error("let a = 1; -a = 3;");
+    tests::assert_compile_error("let a = 1; (a) = 3;");
+    tests::assert_compile_error("1 = 2;");
+    tests::assert_compile_error("let a = 1; a + a += 3;");
+    tests::assert_compile_error("let a = 1; a * a = 3;");
+}
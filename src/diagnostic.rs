@@ -0,0 +1,160 @@
+use std::fmt::{Display, Formatter, Write};
+
+/// A range of bytes in the source code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source code, pointing at the span that caused it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the diagnostic with the source line it points at, underlining the span with carets.
+    ///
+    /// Spans covering several lines are underlined up to the end of their first line.
+    pub fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |index| start + index);
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let line = source[line_start..line_end].trim_end_matches('\r');
+
+        let column = source[line_start..start].chars().count() + 1;
+        let end = self.span.end.clamp(start, line_end);
+        let width = source[start..end].chars().count().max(1);
+        let gutter = " ".repeat(line_number.to_string().len());
+
+        let mut output = String::new();
+        writeln!(output, "{}: {}", self.severity, self.message).unwrap();
+        writeln!(output, "{}--> {}:{}", gutter, line_number, column).unwrap();
+        writeln!(output, "{} |", gutter).unwrap();
+        writeln!(output, "{} | {}", line_number, line).unwrap();
+        writeln!(output, "{} | {}{}", gutter, " ".repeat(column - 1), "^".repeat(width)).unwrap();
+        for note in &self.notes {
+            writeln!(output, "{} = note: {}", gutter, note).unwrap();
+        }
+        if let Some(help) = &self.help {
+            writeln!(output, "{} = help: {}", gutter, help).unwrap();
+        }
+        output
+    }
+}
+
+/// Renders every diagnostic, separated by blank lines
+pub fn render_all(diagnostics: &[Diagnostic], source: &str) -> String {
+    diagnostics.iter()
+        .map(|diagnostic| diagnostic.render(source))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::{Diagnostic, Span};
+
+    #[test]
+    fn caret_under_span() {
+        let source = "let a = 1;\nlet b = a +* 2;\n";
+        let diagnostic = Diagnostic::error("Expected expression.", Span::new(22, 23));
+        assert_eq!(diagnostic.render(source), "\
+error: Expected expression.
+ --> 2:12
+  |
+2 | let b = a +* 2;
+  |            ^
+");
+    }
+
+    #[test]
+    fn notes_and_help() {
+        let source = "{ let a = 1; let a = 2; }";
+        let diagnostic = Diagnostic::error("Already a variable with this name in this scope.", Span::new(17, 18))
+            .with_note("'a' was first declared on line 1")
+            .with_help("pick a different name");
+        assert_eq!(diagnostic.render(source), "\
+error: Already a variable with this name in this scope.
+ --> 1:18
+  |
+1 | { let a = 1; let a = 2; }
+  |                  ^
+  = note: 'a' was first declared on line 1
+  = help: pick a different name
+");
+    }
+
+    #[test]
+    fn end_of_file() {
+        let source = "print(\"é\"";
+        let diagnostic = Diagnostic::error("Expect ')' after arguments.", Span::new(source.len(), source.len()));
+        assert_eq!(diagnostic.render(source), "\
+error: Expect ')' after arguments.
+ --> 1:10
+  |
+1 | print(\"é\"
+  |          ^
+");
+    }
+
+    #[test]
+    fn multiline_span() {
+        let source = "\n\n\n\n\n\n\n\n\nlet s = \"first\nsecond\";";
+        let diagnostic = Diagnostic::error("Bad string", Span::new(17, 31));
+        assert_eq!(diagnostic.render(source), "\
+error: Bad string
+  --> 10:9
+   |
+10 | let s = \"first
+   |         ^^^^^^
+");
+    }
+}
@@ -1,5 +1,12 @@
 use crate::vm::value::Value;
 
+mod control_flow;
+mod embedding;
+mod functions;
+mod limits;
+mod logic;
+mod natives;
+mod serialization;
 mod variables;
 
 fn assert_number(value: &Value, expected: f64) {
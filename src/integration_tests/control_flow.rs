@@ -0,0 +1,116 @@
+use crate::integration_tests::assert_number;
+use crate::vm::interpret;
+
+#[test]
+fn if_else() {
+    let src = "let a = 0; if (1 < 2) a = 1; else a = 2; a".to_string();
+    assert_number(&interpret(src, true).unwrap(), 1.0);
+
+    let src = "let a = 0; if (1 > 2) a = 1; else a = 2; a".to_string();
+    assert_number(&interpret(src, true).unwrap(), 2.0);
+}
+
+#[test]
+fn else_if_chain() {
+    let src = "
+        let a = 5;
+        let result = 0;
+        if (a < 3) {
+            result = 1;
+        } else if (a < 6) {
+            result = 2;
+        } else {
+            result = 3;
+        }
+        result
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 2.0);
+}
+
+#[test]
+fn while_loop() {
+    let src = "
+        let sum = 0;
+        let i = 0;
+        while (i < 5) {
+            let doubled = i * 2;
+            sum = sum + doubled;
+            i = i + 1;
+        }
+        sum
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 20.0);
+}
+
+#[test]
+fn non_bool_condition() {
+    assert!(interpret("if (1) 2;".to_string(), true).is_err());
+    assert!(interpret("while (nil) 2;".to_string(), true).is_err());
+}
+
+#[test]
+fn counted_repeat() {
+    let src = "let count = 0; repeat (4) count = count + 1; count".to_string();
+    assert_number(&interpret(src, true).unwrap(), 4.0);
+
+    let src = "let count = 0; repeat (2.5) count = count + 1; count".to_string();
+    assert_number(&interpret(src, true).unwrap(), 3.0);
+
+    let src = "let count = 0; repeat (-1) count = count + 1; count".to_string();
+    assert_number(&interpret(src, true).unwrap(), 0.0);
+}
+
+#[test]
+fn nested_repeat() {
+    let src = "let count = 0; repeat (3) repeat (4) count = count + 1; count".to_string();
+    assert_number(&interpret(src, true).unwrap(), 12.0);
+}
+
+#[test]
+fn repeat_with_break_and_continue() {
+    let src = "
+        let i = 0;
+        let sum = 0;
+        repeat {
+            i = i + 1;
+            if (i > 9) break;
+            let skipped = i == 4 || i == 6;
+            if (skipped) continue;
+            sum = sum + i;
+        }
+        sum
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 35.0);
+
+    let src = "
+        let count = 0;
+        repeat (10) {
+            let unused = 1;
+            if (count == 3) break;
+            count = count + 1;
+        }
+        count
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 3.0);
+}
+
+#[test]
+fn while_with_break_and_continue() {
+    let src = "
+        let i = 0;
+        let sum = 0;
+        while (true) {
+            i = i + 1;
+            if (i == 3) continue;
+            if (i > 5) break;
+            sum = sum + i;
+        }
+        sum
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 12.0);
+}
+
+#[test]
+fn repeat_count_must_be_number() {
+    assert!(interpret("repeat (nil) 1;".to_string(), true).is_err());
+}
@@ -0,0 +1,182 @@
+use crate::vm::error::{ErrorKind, InterpretError, RuntimeError};
+use crate::vm::value::Value;
+use crate::vm::Vm;
+
+#[test]
+fn eval_keeps_globals() {
+    let mut vm = Vm::new();
+    assert_eq!(vm.eval("let count = 1;"), Ok(Value::Nil));
+    assert_eq!(vm.eval("count = count + 1; count"), Ok(Value::Number(2.0)));
+    assert_eq!(vm.global("count"), Some(&Value::Number(2.0)));
+}
+
+#[test]
+fn host_sets_globals() {
+    let mut vm = Vm::new();
+    vm.set_global("name", Value::from("fops"));
+    assert_eq!(vm.eval("\"Hello, \" + name"), Ok(Value::from("Hello, fops")));
+
+    vm.set_global("name", Value::Number(1.0));
+    assert_eq!(vm.eval("type_of(name)"), Ok(Value::from("number")));
+    assert_eq!(vm.global("missing"), None);
+}
+
+#[test]
+fn compile_once_run_many() {
+    let mut vm = Vm::new();
+    vm.set_global("total", Value::Number(0.0));
+    let chunk = Vm::compile("total = total + 5; total").unwrap();
+
+    assert_eq!(vm.run(&chunk), Ok(Value::Number(5.0)));
+    assert_eq!(vm.run(&chunk), Ok(Value::Number(10.0)));
+}
+
+#[test]
+fn compile_errors() {
+    let mut vm = Vm::new();
+    let diagnostics = Vm::compile("let a = ;").unwrap_err();
+    assert_eq!(diagnostics[0].message, "Expected expression.");
+
+    let Err(InterpretError::Compile { diagnostics: reported, source }) = vm.eval("let a = ;") else {
+        panic!("Expected compile error")
+    };
+    assert_eq!(reported, diagnostics);
+    assert_eq!(source, "let a = ;");
+    let error = vm.eval("let a = ;").unwrap_err();
+    assert_eq!(error.to_string(), "error: Expected expression.\n --> 1:9\n  |\n1 | let a = ;\n  |         ^\n");
+}
+
+#[test]
+fn runtime_errors_leave_the_vm_usable() {
+    let mut vm = Vm::new();
+    assert!(matches!(vm.eval("let a = 1; a + nil"), Err(InterpretError::Runtime(_))));
+    assert_eq!(vm.eval("a + 1"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn call_script_function() {
+    let mut vm = Vm::new();
+    vm.eval("let clicks = 0; fun on_event(name) { clicks = clicks + 1; return name + \"!\"; }").unwrap();
+    assert_eq!(vm.call("on_event", &[Value::from("click")]), Ok(Value::from("click!")));
+    assert_eq!(vm.call("on_event", &[Value::from("hover")]), Ok(Value::from("hover!")));
+    assert_eq!(vm.global("clicks"), Some(&Value::Number(2.0)));
+    assert_eq!(vm.call("type_of", &[Value::Nil]), Ok(Value::from("nil")));
+}
+
+#[test]
+fn call_errors() {
+    let mut vm = Vm::new();
+    vm.eval("fun fail(x) { return x + nil; } let number = 1;").unwrap();
+
+    assert_eq!(vm.call("missing", &[]).unwrap_err().kind, ErrorKind::UndefinedVariable);
+    assert_eq!(vm.call("number", &[]).unwrap_err().kind, ErrorKind::Type);
+    assert_eq!(vm.call("fail", &[]).unwrap_err().kind, ErrorKind::Arity);
+
+    let error = vm.call("fail", &[Value::Number(1.0)]).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::Type);
+    assert_eq!(error.trace[0].function, "fail");
+
+    assert_eq!(vm.eval("number + 1"), Ok(Value::Number(2.0)));
+}
+
+/// Binds `apply(f, x)`, which calls back into the script with `f(x)`
+fn vm_with_apply() -> Vm {
+    let mut vm = Vm::new();
+    vm.define_native_with_vm("apply", 2, |vm, arguments| vm.call_value(&arguments[0], &arguments[1..]));
+    vm
+}
+
+#[test]
+fn nested_calls() {
+    let mut vm = vm_with_apply();
+    let src = "
+        fun double(x) { return x * 2; }
+        fun quadruple(x) { return apply(double, apply(double, x)); }
+        fun twice_quadrupled(x) { return 1 + apply(quadruple, x) + apply(quadruple, x); }
+    ";
+    vm.eval(src).unwrap();
+
+    // Host -> script -> host -> script -> host -> script
+    assert_eq!(vm.call("twice_quadrupled", &[Value::Number(3.0)]), Ok(Value::Number(25.0)));
+    assert_eq!(vm.eval("let a = apply(twice_quadrupled, 1); a"), Ok(Value::Number(9.0)));
+}
+
+#[test]
+fn nested_closures_keep_their_upvalues() {
+    let mut vm = vm_with_apply();
+    let src = "
+        fun counter() {
+            let count = 0;
+            fun increment(by) { count = count + by; return count; }
+            apply(increment, 2);
+            apply(increment, 3);
+            return increment;
+        }
+        let increment = counter();
+    ";
+    vm.eval(src).unwrap();
+    assert_eq!(vm.call("increment", &[Value::Number(10.0)]), Ok(Value::Number(15.0)));
+}
+
+#[test]
+fn nested_error_trace() {
+    let mut vm = vm_with_apply();
+    vm.eval("fun inner(x) {\n  return x + nil;\n}\nfun outer(x) {\n  return apply(inner, x);\n}").unwrap();
+
+    let error = vm.call("outer", &[Value::Number(1.0)]).unwrap_err();
+    assert_eq!(error.traceback(), "\
+Traceback (most recent call last):
+  [Line 5:24] in outer
+  in apply
+  [Line 2:12] in inner
+Type error: Cannot perform addition between 1 and nil");
+
+    let Err(InterpretError::Runtime(error)) = vm.eval("apply(outer, 2)") else { panic!("Expected runtime error") };
+    let functions: Vec<&str> = error.trace.iter().map(|frame| frame.function.as_str()).collect();
+    assert_eq!(functions, ["inner", "apply", "outer", "apply", "script"]);
+    assert_eq!(vm.eval("apply(outer, true)").unwrap_err().to_string(), "[Line 2:12] Cannot perform addition between true and nil");
+}
+
+#[test]
+fn native_errors_after_callbacks() {
+    let mut vm = Vm::new();
+    vm.define_native_with_vm("check", 1, |vm, arguments| {
+        match vm.call_value(&arguments[0], &[])? {
+            Value::Bool(true) => Ok(Value::Nil),
+            _ => Err(RuntimeError::new(ErrorKind::Native, "check failed")),
+        }
+    });
+    vm.eval("fun yes() { return true; } fun no() { return false; }").unwrap();
+
+    assert_eq!(vm.eval("check(yes)"), Ok(Value::Nil));
+    let Err(InterpretError::Runtime(error)) = vm.eval("check(no)") else { panic!("Expected runtime error") };
+    assert_eq!(error.traceback(), "Traceback (most recent call last):\n  [Line 1:9] in script\n  in check\nNative error: check failed");
+
+    let Err(InterpretError::Runtime(error)) = vm.eval("check(1)") else { panic!("Expected runtime error") };
+    assert_eq!(error.trace[0].function, "check");
+    assert_eq!(error.trace[1].function, "script");
+}
+
+#[test]
+fn native_errors_from_the_host() {
+    let mut vm = vm_with_apply();
+    vm.define_native("fail", 1, |_| Err("failed".to_string()));
+    vm.eval("fun via(x) { return apply(fail, x); }").unwrap();
+    let fail = vm.global("fail").unwrap().clone();
+    let apply = vm.global("apply").unwrap().clone();
+    let trace = |error: RuntimeError| error.trace.iter().map(|frame| frame.function.clone()).collect::<Vec<_>>();
+
+    let error = vm.call_value(&fail, &[Value::Nil]).unwrap_err();
+    assert_eq!(error.traceback(), "Traceback (most recent call last):\n  in fail\nNative error: failed");
+    assert_eq!(trace(vm.call_value(&apply, &[fail, Value::Nil]).unwrap_err()), ["fail", "apply"]);
+    assert_eq!(trace(vm.call("via", &[Value::Nil]).unwrap_err()), ["fail", "apply", "via"]);
+}
+
+#[test]
+fn deep_recursion_through_natives() {
+    let mut vm = vm_with_apply();
+    vm.eval("fun down(n) { if (n == 0) { return 0; } return 1 + apply(down, n - 1); }").unwrap();
+    assert_eq!(vm.call("down", &[Value::Number(50.0)]), Ok(Value::Number(50.0)));
+    assert_eq!(vm.call("down", &[Value::Number(1000.0)]).unwrap_err().kind, ErrorKind::StackOverflow);
+    assert_eq!(vm.call("down", &[Value::Number(3.0)]), Ok(Value::Number(3.0)));
+}
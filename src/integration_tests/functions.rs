@@ -0,0 +1,208 @@
+use crate::integration_tests::assert_number;
+use crate::vm::error::{ErrorKind, InterpretError, RuntimeError};
+use crate::vm::value::Value;
+use crate::vm::{interpret, Vm};
+
+#[test]
+fn recursion() {
+    let src = "
+        fun fib(n) {
+            if (n < 2) return n;
+            return fib(n - 1) + fib(n - 2);
+        }
+        fib(15)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 610.0);
+}
+
+#[test]
+fn locals_and_nested_functions() {
+    let src = "
+        fun outer(a) {
+            let b = a * 2;
+            fun inner(c) {
+                let d = c + 1;
+                return d;
+            }
+            return inner(b) + a;
+        }
+        outer(10)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 31.0);
+}
+
+#[test]
+fn functions_are_values() {
+    let src = "
+        fun twice(x) { return x * 2; }
+        let alias = twice;
+        alias(4)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 8.0);
+
+    let src = "fun f() {} f()".to_string();
+    assert_eq!(interpret(src, true), Ok(Value::Nil));
+
+    let src = "fun f() {} f".to_string();
+    assert_eq!(interpret(src, true).unwrap().to_string(), "<fun f>");
+}
+
+#[test]
+fn return_from_loop() {
+    let src = "
+        fun first_above(limit) {
+            let i = 0;
+            while (true) {
+                let square = i * i;
+                if (square > limit) return square;
+                i = i + 1;
+            }
+        }
+        first_above(50)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 64.0);
+}
+
+fn runtime_error(source: &str) -> RuntimeError {
+    match interpret(source.to_string(), false) {
+        Err(InterpretError::Runtime(error)) => error,
+        other => panic!("Expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn call_errors() {
+    assert_eq!(runtime_error("fun f(a) {} f();").kind, ErrorKind::Arity);
+    assert_eq!(runtime_error("fun f(a) {} f(1, 2);").kind, ErrorKind::Arity);
+    assert_eq!(runtime_error("let f = 1; f();").kind, ErrorKind::Type);
+    assert_eq!(runtime_error("fun f() { return f(); } f();").kind, ErrorKind::StackOverflow);
+}
+
+#[test]
+fn stack_trace() {
+    let error = runtime_error("fun inner(x) {\n  return x + nil;\n}\nfun outer() {\n  return inner(1);\n}\nouter();");
+    assert_eq!(error.kind, ErrorKind::Type);
+    assert_eq!(error.traceback(), "\
+Traceback (most recent call last):
+  [Line 7:7] in script
+  [Line 5:17] in outer
+  [Line 2:12] in inner
+Type error: Cannot perform addition between 1 and nil");
+
+    let overflow = runtime_error("fun f() { return f(); } f();");
+    assert_eq!(overflow.trace.len(), 256);
+    assert!(overflow.trace[..255].iter().all(|frame| frame.function == "f"));
+}
+
+#[test]
+fn recovers_after_runtime_error_in_function() {
+    let mut vm = Vm::new();
+    vm.interpret("fun fail() { return nil + 1; }".to_string(), true).unwrap();
+    assert!(vm.interpret("fail()".to_string(), true).is_err());
+
+    let result = vm.interpret("fun ok() { return 5; } ok()".to_string(), true);
+    assert_number(&result.unwrap(), 5.0);
+}
+
+#[test]
+fn counter_closure() {
+    let src = "
+        fun make_counter() {
+            let count = 0;
+            fun increment() {
+                count = count + 1;
+                return count;
+            }
+            return increment;
+        }
+        let counter = make_counter();
+        counter();
+        counter();
+        counter()
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 3.0);
+}
+
+#[test]
+fn independent_closures() {
+    let src = "
+        fun make_counter() {
+            let count = 0;
+            fun increment() {
+                count = count + 1;
+                return count;
+            }
+            return increment;
+        }
+        let first = make_counter();
+        let second = make_counter();
+        first();
+        first();
+        second();
+        first() * 10 + second()
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 32.0);
+}
+
+#[test]
+fn shared_upvalue() {
+    let src = "
+        let get = nil;
+        let set = nil;
+        {
+            let value = 1;
+            fun getter() { return value; }
+            fun setter(new) { value = new; }
+            get = getter;
+            set = setter;
+        }
+        set(5);
+        get()
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 5.0);
+}
+
+#[test]
+fn closures_capture_each_loop_iteration() {
+    let src = "
+        let first = nil;
+        let second = nil;
+        let i = 0;
+        while (i < 2) {
+            let captured = i;
+            fun f() { return captured; }
+            if (i == 0) first = f; else second = f;
+            i = i + 1;
+        }
+        first() * 10 + second()
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 1.0);
+}
+
+#[test]
+fn nested_closures() {
+    let src = "
+        fun outer(x) {
+            fun middle(y) {
+                fun inner(z) { return x + y + z; }
+                return inner;
+            }
+            return middle;
+        }
+        outer(1)(2)(3)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 6.0);
+}
+
+#[test]
+fn closure_as_callback() {
+    let src = "
+        fun apply_twice(f, x) { return f(f(x)); }
+        fun make_adder(n) {
+            fun add(x) { return x + n; }
+            return add;
+        }
+        apply_twice(make_adder(10), 1)
+    ".to_string();
+    assert_number(&interpret(src, true).unwrap(), 21.0);
+}
@@ -0,0 +1,142 @@
+use crate::vm::convert::FromValue;
+use crate::vm::error::{ErrorKind, InterpretError, RuntimeError};
+use crate::vm::value::Value;
+use crate::vm::Vm;
+use std::thread;
+use std::time::Duration;
+
+fn runtime_error(result: Result<Value, InterpretError>) -> RuntimeError {
+    match result {
+        Err(InterpretError::Runtime(error)) => error,
+        other => panic!("Expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn infinite_loop_runs_out_of_fuel() {
+    let mut vm = Vm::new();
+    vm.set_fuel(Some(1000));
+    let error = runtime_error(vm.eval("while (true) {}"));
+    assert_eq!(error.kind, ErrorKind::OutOfFuel);
+    assert_eq!(error.traceback(), "Traceback (most recent call last):\n  [Line 1:8] in script\nOut of fuel: Ran out of fuel");
+    assert_eq!(vm.fuel(), Some(0));
+    assert!(vm.is_suspended());
+}
+
+#[test]
+fn resume_with_more_fuel() {
+    let mut vm = Vm::new();
+    vm.eval("fun add(a, b) { return a + b; }").unwrap();
+    vm.set_fuel(Some(10));
+
+    let mut result = vm.eval("let total = 0; let i = 0; while (i < 50) { total = add(total, i); i = i + 1; } total");
+    let mut resumes = 0;
+    while let Err(InterpretError::Runtime(RuntimeError { kind: ErrorKind::OutOfFuel, .. })) = result {
+        resumes += 1;
+        vm.set_fuel(Some(10));
+        result = vm.resume().map_err(InterpretError::Runtime);
+    }
+
+    assert_eq!(result, Ok(Value::Number(1225.0)));
+    assert!(resumes > 50);
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.eval("i"), Ok(Value::Number(50.0)));
+}
+
+#[test]
+fn unlimited_fuel() {
+    let mut vm = Vm::new();
+    vm.set_fuel(Some(0));
+    assert_eq!(runtime_error(vm.eval("1")).kind, ErrorKind::OutOfFuel);
+
+    vm.set_fuel(None);
+    assert_eq!(vm.resume(), Ok(Value::Number(1.0)));
+    assert_eq!(vm.resume().unwrap_err().kind, ErrorKind::Internal);
+}
+
+#[test]
+fn new_run_discards_suspended_run() {
+    let mut vm = Vm::new();
+    vm.eval("fun double(x) { return x * 2; }").unwrap();
+    vm.set_fuel(Some(5));
+    assert_eq!(runtime_error(vm.eval("fun f() { while (true) {} } f();")).kind, ErrorKind::OutOfFuel);
+
+    vm.set_fuel(None);
+    assert_eq!(vm.eval("2 + 3"), Ok(Value::Number(5.0)));
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.resume().unwrap_err().kind, ErrorKind::Internal);
+
+    let spin = "{ let i = 0; let total = 0; while (true) { total = total + i; i = i + 1; } }";
+    vm.set_fuel(Some(30));
+    assert_eq!(runtime_error(vm.eval(spin)).kind, ErrorKind::OutOfFuel);
+    vm.set_fuel(None);
+    assert_eq!(vm.eval("{ let a = 7; a + 1; } 5"), Ok(Value::Number(5.0)));
+    assert_eq!(vm.eval("let r = 0; { let a = 7; r = a + 1; } r"), Ok(Value::Number(8.0)));
+
+    vm.set_fuel(Some(30));
+    assert_eq!(runtime_error(vm.eval(spin)).kind, ErrorKind::OutOfFuel);
+    vm.set_fuel(None);
+    assert_eq!(vm.call("double", &[Value::Number(4.0)]), Ok(Value::Number(8.0)));
+    assert!(!vm.is_suspended());
+}
+
+#[test]
+fn fuel_running_out_in_a_native_callback_unwinds() {
+    let mut vm = Vm::new();
+    vm.define_native_with_vm("apply", 1, |vm, arguments| vm.call_value(&arguments[0], &[]));
+    vm.eval("fun spin() { while (true) {} }").unwrap();
+    vm.set_fuel(Some(100));
+
+    let error = runtime_error(vm.eval("apply(spin);"));
+    assert_eq!(error.kind, ErrorKind::OutOfFuel);
+    assert!(!vm.is_suspended());
+
+    vm.set_fuel(Some(100));
+    assert_eq!(vm.eval("1 + 1"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn fuel_running_out_under_a_native_called_by_the_host_unwinds() {
+    let mut vm = Vm::new();
+    vm.define_native_with_vm("apply_plus_one", 1, |vm, arguments| {
+        let result = vm.call_value(&arguments[0], &[])?;
+        Ok(Value::Number(f64::from_value(&result).unwrap() + 1.0))
+    });
+    vm.eval("fun hundred() { let i = 0; while (i < 100) { i = i + 1; } return i; }").unwrap();
+    let native = vm.global("apply_plus_one").unwrap().clone();
+    let hundred = vm.global("hundred").unwrap().clone();
+
+    vm.set_fuel(Some(50));
+    let error = vm.call_value(&native, std::slice::from_ref(&hundred)).unwrap_err();
+    assert_eq!(error.kind, ErrorKind::OutOfFuel);
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.resume().unwrap_err().kind, ErrorKind::Internal);
+
+    vm.set_fuel(None);
+    assert_eq!(vm.call_value(&native, &[hundred]), Ok(Value::Number(101.0)));
+}
+
+#[test]
+fn interrupt_from_another_thread() {
+    let mut vm = Vm::new();
+    let handle = vm.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+
+    let error = runtime_error(vm.eval("fun spin() { while (true) {} } spin();"));
+    interrupter.join().unwrap();
+    assert_eq!(error.kind, ErrorKind::Interrupted);
+    assert_eq!(error.trace[0].function, "spin");
+    assert!(!vm.is_suspended());
+    assert_eq!(vm.eval("1 + 1"), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn interrupt_before_running() {
+    let mut vm = Vm::new();
+    vm.interrupt_handle().interrupt();
+    assert_eq!(runtime_error(vm.eval("1")).kind, ErrorKind::Interrupted);
+    assert_eq!(vm.eval("1"), Ok(Value::Number(1.0)));
+}
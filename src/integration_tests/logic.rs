@@ -0,0 +1,31 @@
+use crate::vm::error::InterpretError;
+use crate::vm::interpret;
+use crate::vm::value::Value;
+
+fn eval(source: &str) -> Result<Value, InterpretError> {
+    interpret(source.to_string(), true)
+}
+
+#[test]
+fn and() {
+    assert_eq!(eval("true && true"), Ok(Value::Bool(true)));
+    assert_eq!(eval("true && false"), Ok(Value::Bool(false)));
+    assert_eq!(eval("false && true"), Ok(Value::Bool(false)));
+    assert_eq!(eval("1 < 2 && 2 < 3"), Ok(Value::Bool(true)));
+}
+
+#[test]
+fn or() {
+    assert_eq!(eval("false || false"), Ok(Value::Bool(false)));
+    assert_eq!(eval("false || true"), Ok(Value::Bool(true)));
+    assert_eq!(eval("true || false"), Ok(Value::Bool(true)));
+    assert_eq!(eval("false || true && false"), Ok(Value::Bool(false)));
+}
+
+#[test]
+fn short_circuit() {
+    assert_eq!(eval("false && undefined"), Ok(Value::Bool(false)));
+    assert_eq!(eval("true || undefined"), Ok(Value::Bool(true)));
+    assert!(eval("true && undefined").is_err());
+    assert!(eval("false || undefined").is_err());
+}
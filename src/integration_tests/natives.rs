@@ -0,0 +1,134 @@
+use crate::vm::error::{ErrorKind, InterpretError};
+use crate::vm::value::Value;
+use crate::vm::{interpret, Vm};
+
+fn eval(source: &str) -> Result<Value, InterpretError> {
+    interpret(source.to_string(), true)
+}
+
+#[test]
+fn type_of() {
+    assert_eq!(eval("type_of(1)"), Ok(Value::from("number")));
+    assert_eq!(eval("type_of(true)"), Ok(Value::from("bool")));
+    assert_eq!(eval("type_of(nil)"), Ok(Value::from("nil")));
+    assert_eq!(eval("type_of(\"fops\")"), Ok(Value::from("string")));
+    assert_eq!(eval("fun f() {} type_of(f)"), Ok(Value::from("function")));
+    assert_eq!(eval("type_of(type_of)"), Ok(Value::from("native")));
+}
+
+#[test]
+fn to_string() {
+    assert_eq!(eval("to_string(1.5)"), Ok(Value::from("1.5")));
+    assert_eq!(eval("to_string(nil) + to_string(false)"), Ok(Value::from("nilfalse")));
+    assert_eq!(eval("to_string(println)"), Ok(Value::from("<native println>")));
+}
+
+#[test]
+fn print_returns_nil() {
+    assert_eq!(eval("print(\"\")"), Ok(Value::Nil));
+    assert_eq!(eval("println(\"\")"), Ok(Value::Nil));
+}
+
+#[test]
+fn clock() {
+    let src = "let start = clock(); clock() >= start".to_string();
+    assert_eq!(interpret(src, true), Ok(Value::Bool(true)));
+}
+
+#[test]
+fn arity_is_checked() {
+    assert!(eval("clock(1)").is_err());
+    assert!(eval("println()").is_err());
+    assert!(eval("type_of(1, 2)").is_err());
+}
+
+#[test]
+fn custom_native() {
+    fn sum(arguments: &[Value]) -> Result<Value, String> {
+        match arguments {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a + b)),
+            _ => Err("sum expects two numbers".to_string()),
+        }
+    }
+
+    let mut vm = Vm::new();
+    vm.define_native("sum", 2, sum);
+    assert_eq!(vm.interpret("sum(1, 2) * 2".to_string(), true), Ok(Value::Number(6.0)));
+    assert!(vm.interpret("sum(1, nil)".to_string(), true).is_err());
+    assert_eq!(vm.interpret("sum(3, 4)".to_string(), true), Ok(Value::Number(7.0)));
+}
+
+#[test]
+fn bound_closure() {
+    let mut vm = Vm::new();
+    vm.bind_native("repeat_string", |text: String, times: usize| text.repeat(times));
+    vm.bind_native("half", |number: f64| number / 2.0);
+    assert_eq!(vm.eval("repeat_string(\"ab\", half(6))"), Ok(Value::from("ababab")));
+    assert_eq!(vm.eval("type_of(repeat_string)"), Ok(Value::from("native")));
+}
+
+#[test]
+fn bound_closure_with_lists() {
+    let mut vm = Vm::new();
+    vm.bind_native("range", |count: u32| (0..count).collect::<Vec<u32>>());
+    vm.bind_native("sum", |numbers: Vec<f64>| numbers.iter().sum::<f64>());
+    assert_eq!(vm.eval("sum(range(5))"), Ok(Value::Number(10.0)));
+    assert_eq!(vm.eval("type_of(range(2))"), Ok(Value::from("list")));
+    assert_eq!(vm.eval("range(3) == range(3)"), Ok(Value::Bool(true)));
+    assert_eq!(vm.eval("range(3)").unwrap().to_string(), "[0, 1, 2]");
+}
+
+#[test]
+fn bound_closure_with_wide_integers() {
+    let mut vm = Vm::new();
+    vm.bind_native("next", |number: i64| number + 1);
+    vm.bind_native("checked_next", |number: u64| number.checked_add(1).ok_or("overflow"));
+
+    // 2^53 - 1 and 2^53 are exact, but 2^53 + 1 isn't a number and would round back down to 2^53
+    assert_eq!(vm.eval("next(9007199254740991)"), Ok(Value::Number(9007199254740992.0)));
+    assert_eq!(vm.eval("checked_next(-1 + 9007199254740992)"), Ok(Value::Number(9007199254740992.0)));
+    assert_eq!(vm.eval("next(-9007199254740992)"), Ok(Value::Number(-9007199254740991.0)));
+    assert!(vm.eval("next(-9007199254740994)").is_err());
+
+    let Err(InterpretError::Runtime(error)) = vm.eval("next(9007199254740992)") else { panic!("Expected runtime error") };
+    assert_eq!(error.kind, ErrorKind::Native);
+    assert_eq!(error.message, "9007199254740993 is too large to be represented exactly as a number");
+    let Err(InterpretError::Runtime(error)) = vm.eval("checked_next(9007199254740992)") else { panic!("Expected runtime error") };
+    assert_eq!(error.message, "9007199254740993 is too large to be represented exactly as a number");
+}
+
+#[test]
+fn bound_closure_checks_arguments() {
+    let mut vm = Vm::new();
+    vm.bind_native("greet", |name: String, excited: Option<bool>| {
+        format!("Hello, {}{}", name, if excited == Some(true) { "!" } else { "." })
+    });
+    assert_eq!(vm.eval("greet(\"fops\", nil)"), Ok(Value::from("Hello, fops.")));
+
+    let Err(InterpretError::Runtime(error)) = vm.eval("greet(\"fops\", 1)") else { panic!("Expected runtime error") };
+    assert_eq!(error.kind, ErrorKind::Native);
+    assert_eq!(error.message, "Argument 2 of greet: expected bool or nil, got number");
+
+    let Err(InterpretError::Runtime(error)) = vm.eval("greet(\"fops\")") else { panic!("Expected runtime error") };
+    assert_eq!(error.kind, ErrorKind::Arity);
+}
+
+#[test]
+fn bound_closure_errors_and_state() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    let mut vm = Vm::new();
+    vm.bind_native("record", move || counter.set(counter.get() + 1));
+    vm.bind_native("checked_sqrt", |number: f64| {
+        if number < 0.0 { Err(format!("Can't take the square root of {}", number)) } else { Ok(number.sqrt()) }
+    });
+
+    assert_eq!(vm.eval("record(); record()"), Ok(Value::Nil));
+    assert_eq!(calls.get(), 2);
+    assert_eq!(vm.eval("checked_sqrt(9)"), Ok(Value::Number(3.0)));
+    let Err(InterpretError::Runtime(error)) = vm.eval("checked_sqrt(-1)") else { panic!("Expected runtime error") };
+    assert_eq!(error.message, "Can't take the square root of -1");
+}
@@ -0,0 +1,31 @@
+use crate::bytecode::chunk::Chunk;
+use crate::integration_tests::assert_number;
+use crate::vm::run;
+use crate::vm::value::Value;
+
+/// Compiles the source, then runs it after a trip through the serialized format
+fn run_serialized(source: &str) -> Result<Value, String> {
+    let chunk = crate::compiler::compile(source, true).unwrap();
+    let bytes = chunk.serialize()?;
+    run(&Chunk::deserialize(&bytes)?).map_err(|error| error.to_string())
+}
+
+#[test]
+fn serialized_program() {
+    let src = "
+        let greeting = \"Hello\";
+        fun make_adder(n) {
+            fun add(x) { return x + n; }
+            return add;
+        }
+        let add_two = make_adder(2);
+        add_two(40)
+    ";
+    assert_number(&run_serialized(src).unwrap(), 42.0);
+}
+
+#[test]
+fn serialized_strings() {
+    let src = "let a = \"fops\"; a + \"!\"";
+    assert_eq!(run_serialized(src).unwrap().to_string(), "fops!");
+}
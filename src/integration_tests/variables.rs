@@ -1,5 +1,5 @@
 use crate::integration_tests::assert_number;
-use crate::vm::value::Value;
+use crate::vm::error::{ErrorKind, InterpretError};
 
 #[test]
 fn variable_assignment() {
@@ -7,3 +7,92 @@ fn variable_assignment() {
     let result = crate::vm::interpret(src, true);
     assert_number(&result.unwrap(), 5.0);
 }
+
+#[test]
+fn local_reassignment() {
+    let src = "let a = 5; a = a + 1; a".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 6.0);
+}
+
+#[test]
+fn globals_persist_between_runs() {
+    let mut vm = crate::vm::Vm::new();
+    vm.interpret("let x = 1;".to_string(), true).unwrap();
+    vm.interpret("x = x + 1;".to_string(), true).unwrap();
+    let result = vm.interpret("x".to_string(), true);
+    assert_number(&result.unwrap(), 2.0);
+}
+
+#[test]
+fn undefined_global() {
+    assert!(crate::vm::interpret("x".to_string(), true).is_err());
+    assert!(crate::vm::interpret("x = 1;".to_string(), true).is_err());
+}
+
+#[test]
+fn runtime_error_position() {
+    let result = crate::vm::interpret("let a = 1;\nlet b = a + x;".to_string(), true);
+    let Err(InterpretError::Runtime(error)) = result else { panic!("Expected runtime error, got {:?}", result) };
+    assert_eq!(error.kind, ErrorKind::UndefinedVariable);
+    assert_eq!(error.to_string(), "[Line 2:13] Undefined variable 'x'");
+}
+
+#[test]
+fn block_scoped_locals() {
+    let src = "let a = 1; { let a = 2; let b = a; } a".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 1.0);
+}
+
+#[test]
+fn assignment_is_an_expression() {
+    let src = "let a = 1; let b = 2; a = b = 3; a + b".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 6.0);
+
+    let src = "let a = 1; (a = 4) * 2".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 8.0);
+}
+
+#[test]
+fn compound_assignment() {
+    let src = "let a = 10; a += 5; a -= 3; a *= 4; a /= 8; a".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 6.0);
+
+    let src = "let a = 2; a *= a += 1; a".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 6.0);
+}
+
+#[test]
+fn compound_assignment_of_strings() {
+    let src = "let s = \"foo\"; s += \"bar\"; s".to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_eq!(result.unwrap().to_string(), "foobar");
+}
+
+#[test]
+fn string_escapes() {
+    let src = r#"let s = "tab\tquote\"\\\u{1F600}"; s + "é""#.to_string();
+    let result = crate::vm::interpret(src, true);
+    assert_eq!(result.unwrap().to_string(), "tab\tquote\"\\😀é");
+}
+
+#[test]
+fn many_string_constants() {
+    let mut src: String = (0..1000).map(|i| format!("let s{} = \"string {}\";", i % 100, i)).collect();
+    src.push_str("s99 + s0");
+    let result = crate::vm::interpret(src, true);
+    assert_eq!(result.unwrap().to_string(), "string 999string 900");
+}
+
+#[test]
+fn globals_and_closures_past_256_constants() {
+    let mut src: String = (0..300).map(|i| format!("\"{}\";", i)).collect();
+    src.push_str("let x = 1; x = x + 1; let r = 0; { let a = 40; fun g() { return a + x; } r = g(); } r");
+    let result = crate::vm::interpret(src, true);
+    assert_number(&result.unwrap(), 42.0);
+}
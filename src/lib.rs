@@ -0,0 +1,25 @@
+//! An embeddable interpreter for the fops language.
+//!
+//! ```
+//! use fops::{Value, Vm};
+//!
+//! let mut vm = Vm::new();
+//! vm.set_global("base", Value::Number(40.0));
+//! assert_eq!(vm.eval("let answer = base + 2; answer"), Ok(Value::Number(42.0)));
+//! assert_eq!(vm.global("answer"), Some(&Value::Number(42.0)));
+//! ```
+
+pub mod bytecode;
+pub mod compiler;
+pub mod diagnostic;
+mod scanner;
+pub mod vm;
+#[cfg(test)]
+mod integration_tests;
+
+pub use crate::bytecode::chunk::Chunk;
+pub use crate::diagnostic::Diagnostic;
+pub use crate::vm::convert::{FromValue, IntoValue};
+pub use crate::vm::error::{ErrorKind, InterpretError, RuntimeError};
+pub use crate::vm::value::Value;
+pub use crate::vm::{InterruptHandle, Vm};
@@ -1,45 +1,91 @@
-use crate::bytecode::disassembler;
+use fops::bytecode::{assembler, disassembler};
+use fops::{compiler, diagnostic, vm, Chunk, InterpretError};
 use std::ffi::OsStr;
 use std::path::Path;
+use std::process::exit;
 use std::{env, fs};
 
-pub mod bytecode;
-mod scanner;
-pub mod vm;
-mod compiler;
 mod repl;
-#[cfg(test)]
-mod integration_tests;
 
 fn main() {
     let args: Vec<String> = env::args().skip(1).collect();
 
-    if let Some(arg) = args.get(0) {
-        let path = Path::new(&arg);
-
-        // This should probably be its own thing
-        if path.extension() == Some(OsStr::new("bin")) {
-            let bytes = fs::read(Box::from(path)).expect("Failed to read file");
-
-            if env::var("DISASSEMBLE").is_ok() {
-                disassembler::disassemble(bytes);
-            } else {
-                todo!("Fix running binary")
-                /*match vm::run(&bytes.into()) {
-                    Ok(value) => println!("Program completed with value {:?}", value),
-                    Err(error) => eprintln!("Runtime error: {}", error),
-                }*/
+    match args.as_slice() {
+        [] => repl::start(),
+        [command, source, flag, output] if command == "compile" && flag == "-o" => compile_file(source, output),
+        [command, ..] if command == "compile" => {
+            eprintln!("Usage: fops compile <source> -o <output>");
+            exit(1);
+        }
+        [command, source, flag, output] if command == "asm" && flag == "-o" => assemble_file(source, output),
+        [command, ..] if command == "asm" => {
+            eprintln!("Usage: fops asm <source> -o <output>");
+            exit(1);
+        }
+        [path, ..] => run_file(Path::new(path)),
+    }
+}
+
+fn run_file(path: &Path) {
+    // This should probably be its own thing
+    if path.extension() == Some(OsStr::new("bin")) {
+        let bytes = fs::read(path).expect("Failed to read file");
+        let chunk = match Chunk::deserialize(&bytes) {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                eprintln!("Failed to load {}: {}", path.display(), error);
+                exit(1);
             }
+        };
 
-            return
+        if env::var("DISASSEMBLE").is_ok() {
+            print!("{}", disassembler::disassemble(&chunk, "script"));
         } else {
-            let string = fs::read_to_string(path).expect("Failed to read file");
-            match vm::interpret(string, false) {
-                Ok(value) => { println!("Exited with value: {}", value.to_string()); },
-                Err(error) => { println!("{}", error); }
-            };
+            match vm::run(&chunk) {
+                Ok(value) => { println!("Exited with value: {}", value); },
+                Err(error) => { println!("{}", error.traceback()); }
+            }
         }
     } else {
-        repl::start()
+        let string = fs::read_to_string(path).expect("Failed to read file");
+        match vm::interpret(string, false) {
+            Ok(value) => { println!("Exited with value: {}", value); },
+            Err(InterpretError::Runtime(error)) => { println!("{}", error.traceback()); }
+            Err(error @ InterpretError::Compile { .. }) => { print!("{}", error); }
+        };
+    }
+}
+
+/// Compiles a source file and writes the serialized chunk to `output`
+fn compile_file(source: &str, output: &str) {
+    let string = fs::read_to_string(source).expect("Failed to read file");
+    let chunk = match compiler::compile(&string, false) {
+        Ok(chunk) => chunk,
+        Err(diagnostics) => {
+            eprint!("{}", diagnostic::render_all(&diagnostics, &string));
+            exit(1);
+        }
+    };
+
+    match chunk.serialize() {
+        Ok(bytes) => fs::write(output, bytes).expect("Failed to write file"),
+        Err(error) => {
+            eprintln!("Failed to serialize {}: {}", source, error);
+            exit(1);
+        }
+    }
+}
+
+/// Assembles a file in the disassembler's text format and writes the serialized chunk to `output`
+fn assemble_file(source: &str, output: &str) {
+    let string = fs::read_to_string(source).expect("Failed to read file");
+    let bytes = assembler::assemble(&string).and_then(|chunk| chunk.serialize());
+
+    match bytes {
+        Ok(bytes) => fs::write(output, bytes).expect("Failed to write file"),
+        Err(error) => {
+            eprintln!("Failed to assemble {}: {}", source, error);
+            exit(1);
+        }
     }
 }
@@ -1,8 +1,10 @@
 use std::io;
 use std::io::Write;
-use crate::vm;
+use fops::{InterpretError, Vm};
 
 pub fn start() {
+    let mut vm = Vm::new();
+
     loop {
         print!("> ");
         io::stdout().flush().unwrap();
@@ -11,9 +13,10 @@ pub fn start() {
         let stdin = io::stdin();
         stdin.read_line(&mut buffer).expect("Failure while reading stdin");
 
-        match vm::interpret(buffer, true) {
-            Ok(value) => println!("{}", value.to_string()),
-            Err(error) => println!("Runtime error: {}", error),
+        match vm.interpret(buffer, true) {
+            Ok(value) => println!("{}", value),
+            Err(InterpretError::Runtime(error)) => println!("{}", error.traceback()),
+            Err(error @ InterpretError::Compile { .. }) => print!("{}", error),
         }
     }
 }
\ No newline at end of file
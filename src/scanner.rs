@@ -1,3 +1,4 @@
+use crate::diagnostic::Span;
 use crate::scanner::TokenType::*;
 use strum::VariantArray;
 
@@ -6,6 +7,13 @@ pub struct Scanner<'a> {
     current_token_start: usize,
     current_token_end: usize,
     line: usize,
+    /// The last byte offset on the current line whose column was worked out, and that column.
+    /// Columns are counted on from there, as tokens are scanned in order.
+    column_offset: usize,
+    column: usize,
+    /// Position of the first character of the current token
+    token_line: usize,
+    token_column: usize,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -13,16 +21,23 @@ pub struct Token<'a> {
     pub string: &'a str,
     pub token_type: TokenType,
     pub line: usize,
+    /// Counted in characters from the start of the line, starting at 1
+    pub column: usize,
+    /// Where the token is in the source, which for scanner errors is the offending text rather than `string`
+    pub span: Span,
 }
 
 pub const PLACEHOLDER_TOKEN: Token = Token {
     string: "",
     token_type: EOF,
-    line: 0
+    line: 0,
+    column: 0,
+    span: Span::new(0, 0),
 };
 
 #[derive(Debug, PartialEq, Copy, Clone, VariantArray)]
 #[rustfmt::skip]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     // Single character tokens
     TokenLeftParen, TokenRightParen,
@@ -36,11 +51,14 @@ pub enum TokenType {
     TokenLess, TokenLessEqual,
     TokenGreater, TokenGreaterEqual,
     TokenAmpAmp, TokenPipePipe,
+    TokenMinusEqual, TokenPlusEqual,
+    TokenSlashEqual, TokenAsteriskEqual,
 
     // Literals
     TokenIdentifier, TokenString, TokenNumber,
 
     // Keywords
+    TokenBreak, TokenContinue,
     TokenElse, TokenFalse, TokenFun, TokenLet, TokenNil,
     TokenIf, TokenRepeat, TokenReturn, TokenTrue, TokenWhile,
     
@@ -55,6 +73,10 @@ impl<'a> Scanner<'a> {
             current_token_start: 0,
             current_token_end: 0,
             line: 1,
+            column_offset: 0,
+            column: 1,
+            token_line: 1,
+            token_column: 1,
         }
     }
 
@@ -62,7 +84,8 @@ impl<'a> Scanner<'a> {
         // Skip whitespace
         loop {
             if self.is_at_end() {
-                return Token { token_type: EOF, string: "", line: self.line };
+                let end = self.current_token_end;
+                return Token { token_type: EOF, string: "", line: self.line, column: self.column_at(end), span: Span::new(end, end) };
             }
             let c = self.peek();
             match c {
@@ -70,8 +93,8 @@ impl<'a> Scanner<'a> {
                     self.advance();
                 }
                 '\n' => {
-                    self.line += 1;
                     self.advance();
+                    self.new_line();
                 }
                 '/' => {
                     if self.peek_next() == Some('/') {
@@ -87,6 +110,8 @@ impl<'a> Scanner<'a> {
         }
 
         self.current_token_start = self.current_token_end;
+        self.token_line = self.line;
+        self.token_column = self.column_at(self.current_token_start);
 
         let c = self.peek();
         self.advance();
@@ -105,10 +130,34 @@ impl<'a> Scanner<'a> {
             ';' => self.make_token(TokenSemicolon),
             ',' => self.make_token(TokenComma),
             '.' => self.make_token(TokenDot),
-            '-' => self.make_token(TokenMinus),
-            '+' => self.make_token(TokenPlus),
-            '/' => self.make_token(TokenSlash),
-            '*' => self.make_token(TokenAsterisk),
+            '-' => {
+                if self.match_next('=') {
+                    self.make_token(TokenMinusEqual)
+                } else {
+                    self.make_token(TokenMinus)
+                }
+            }
+            '+' => {
+                if self.match_next('=') {
+                    self.make_token(TokenPlusEqual)
+                } else {
+                    self.make_token(TokenPlus)
+                }
+            }
+            '/' => {
+                if self.match_next('=') {
+                    self.make_token(TokenSlashEqual)
+                } else {
+                    self.make_token(TokenSlash)
+                }
+            }
+            '*' => {
+                if self.match_next('=') {
+                    self.make_token(TokenAsteriskEqual)
+                } else {
+                    self.make_token(TokenAsterisk)
+                }
+            }
             '!' => {
                 if self.match_next('=') {
                     self.make_token(TokenBangEqual)
@@ -157,16 +206,11 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        self.source.as_bytes()[self.current_token_end] as char
+        self.source[self.current_token_end..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> Option<char> {
-        let i = self.current_token_end + 1;
-        if self.source.len() <= i {
-            None
-        } else {
-            Some(self.source.as_bytes()[i] as char)
-        }
+        self.source[self.current_token_end..].chars().nth(1)
     }
 
     fn skip_until_line_end(&mut self) {
@@ -179,14 +223,16 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) {
-        self.current_token_end += 1;
+        if !self.is_at_end() {
+            self.current_token_end += self.peek().len_utf8();
+        }
     }
 
     fn match_next(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.as_bytes()[self.current_token_end] as char != expected {
+        if self.peek() != expected {
             return false;
         }
         self.advance();
@@ -198,11 +244,26 @@ impl<'a> Scanner<'a> {
     }
 
     fn string_literal(&mut self) -> Token<'a> {
+        // Only the first invalid escape is reported, but the rest of the string is still skipped
+        let mut invalid_escape = None;
+
         while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.peek();
+            if c == '\\' {
+                let start = self.current_token_end;
+                let (line, column) = (self.line, self.column_at(start));
+                self.advance();
+                if let Err(message) = self.escape_sequence() {
+                    let span = Span::new(start, self.current_token_end);
+                    invalid_escape.get_or_insert(Token { string: message, token_type: ScannerError, line, column, span });
+                }
+                continue;
             }
+
             self.advance();
+            if c == '\n' {
+                self.new_line();
+            }
         }
 
         if self.is_at_end() {
@@ -210,14 +271,58 @@ impl<'a> Scanner<'a> {
         }
 
         self.advance();
-        self.make_token(TokenString)
+        invalid_escape.unwrap_or_else(|| self.make_token(TokenString))
+    }
+
+    /// Skips the escape sequence following a backslash, failing if it isn't one of `\n \t \\ \" \u{...}`
+    fn escape_sequence(&mut self) -> Result<(), &'static str> {
+        match self.peek() {
+            'n' | 't' | '\\' | '"' => {
+                self.advance();
+                Ok(())
+            }
+            'u' => {
+                self.advance();
+                self.unicode_escape()
+            }
+            // Leave line breaks and the end of the string to the caller
+            '\n' | '\0' => Err("Invalid escape sequence"),
+            _ => {
+                self.advance();
+                Err("Invalid escape sequence")
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<(), &'static str> {
+        if !self.match_next('{') {
+            return Err("Expected '{' after \\u");
+        }
+
+        let digits_start = self.current_token_end;
+        while !self.is_at_end() && self.peek().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits = &self.source[digits_start..self.current_token_end];
+
+        if !self.match_next('}') {
+            return Err("Expected '}' after unicode escape");
+        }
+        if digits.is_empty() || digits.len() > 6 {
+            return Err("Unicode escape must have 1 to 6 hex digits");
+        }
+
+        match u32::from_str_radix(digits, 16).ok().and_then(char::from_u32) {
+            Some(_) => Ok(()),
+            None => Err("Invalid unicode code point"),
+        }
     }
 
     fn identifier(&mut self) -> Token<'a> {
         loop {
             if self.is_at_end() { break; }
             let c = self.peek();
-            if !c.is_ascii_alphanumeric() && c != '_' {
+            if !c.is_alphanumeric() && c != '_' {
                 break;
             }
             self.advance();
@@ -245,7 +350,9 @@ impl<'a> Scanner<'a> {
         Token {
             string: self.get_current_string(),
             token_type,
-            line: self.line,
+            line: self.token_line,
+            column: self.token_column,
+            span: Span::new(self.current_token_start, self.current_token_end),
         }
     }
     
@@ -253,16 +360,35 @@ impl<'a> Scanner<'a> {
         Token {
             string: message,
             token_type: ScannerError,
-            line: self.line,
+            line: self.token_line,
+            column: self.token_column,
+            span: Span::new(self.current_token_start, self.current_token_end),
         }
     }
 
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.column_offset = self.current_token_end;
+        self.column = 1;
+    }
+
+    /// Column of the byte at `offset` on the current line, not counting UTF-8 continuation bytes as characters.
+    /// Offsets must not go backwards within a line.
+    fn column_at(&mut self, offset: usize) -> usize {
+        let skipped = &self.source.as_bytes()[self.column_offset..offset];
+        self.column += skipped.iter().filter(|byte| (**byte & 0xC0) != 0x80).count();
+        self.column_offset = offset;
+        self.column
+    }
+
     fn get_current_string(&self) -> &'a str {
         &self.source[self.current_token_start..self.current_token_end]
     }
 
     fn identifier_type(str: &str) -> TokenType {
         match str {
+            "break" => TokenBreak,
+            "continue" => TokenContinue,
             "else" => TokenElse,
             "false" => TokenFalse,
             "fun" => TokenFun,
@@ -278,6 +404,33 @@ impl<'a> Scanner<'a> {
     }
 }
 
+/// The value of a string literal token, with the quotes removed and escape sequences decoded.
+/// The escape sequences must have been validated by the scanner.
+pub fn string_value(literal: &str) -> String {
+    let mut value = String::with_capacity(literal.len());
+    let mut chars = literal[1..literal.len() - 1].chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => value.push('\n'),
+            Some('t') => value.push('\t'),
+            Some('u') => {
+                let digits: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                value.extend(u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32));
+            }
+            // \\ and \"
+            Some(escaped) => value.push(escaped),
+            None => {}
+        }
+    }
+    value
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -322,7 +475,7 @@ mod tests {
 
     #[test]
     fn one_or_two_character_tokens() {
-        let source = "! != = == < <= > >= && ||";
+        let source = "! != = == < <= > >= && || -= += /= *=";
         let mut scanner = Scanner::new(source);
 
         match_token(&mut scanner, TokenBang, 1);
@@ -335,6 +488,10 @@ mod tests {
         match_token(&mut scanner, TokenGreaterEqual, 1);
         match_token(&mut scanner, TokenAmpAmp, 1);
         match_token(&mut scanner, TokenPipePipe, 1);
+        match_token(&mut scanner, TokenMinusEqual, 1);
+        match_token(&mut scanner, TokenPlusEqual, 1);
+        match_token(&mut scanner, TokenSlashEqual, 1);
+        match_token(&mut scanner, TokenAsteriskEqual, 1);
         assert_eq!(scanner.next().token_type, EOF);
     }
 
@@ -343,7 +500,7 @@ mod tests {
         let source = "\"Hello,\nworld!\"\n\"Hello again!!\"";
         let mut scanner = Scanner::new(source);
 
-        match_full_token(&mut scanner, TokenString, "\"Hello,\nworld!\"", 2);
+        match_full_token(&mut scanner, TokenString, "\"Hello,\nworld!\"", 1);
         match_full_token(&mut scanner, TokenString, "\"Hello again!!\"", 3);
         assert_eq!(scanner.next().token_type, EOF);
     }
@@ -377,9 +534,11 @@ mod tests {
 
     #[test]
     fn keywords() {
-        let source = "else false fun let if repeat return true while";
+        let source = "break continue else false fun let if repeat return true while";
         let mut scanner = Scanner::new(source);
 
+        match_full_token(&mut scanner, TokenBreak, "break", 1);
+        match_full_token(&mut scanner, TokenContinue, "continue", 1);
         match_full_token(&mut scanner, TokenElse, "else", 1);
         match_full_token(&mut scanner, TokenFalse, "false", 1);
         match_full_token(&mut scanner, TokenFun, "fun", 1);
@@ -400,4 +559,88 @@ mod tests {
         match_token(&mut scanner, TokenEqualEqual, 2);
         assert_eq!(scanner.next().token_type, EOF);
     }
+
+    #[test]
+    fn columns() {
+        let source = "let x = 1;\n  \"é\" + y;";
+        let mut scanner = Scanner::new(source);
+        let positions: Vec<(usize, usize)> = std::iter::from_fn(|| {
+            let token = scanner.next();
+            (token.token_type != EOF).then_some((token.line, token.column))
+        }).collect();
+
+        assert_eq!(positions, vec![(1, 1), (1, 5), (1, 7), (1, 9), (1, 10), (2, 3), (2, 7), (2, 9), (2, 10)]);
+    }
+
+    #[test]
+    fn long_line_columns() {
+        let source = "é+".repeat(200_000) + "\n  \"a\\q\"";
+        let mut scanner = Scanner::new(&source);
+        for column in (1..400_000).step_by(2) {
+            assert_eq!(scanner.next().column, column);
+            assert_eq!(scanner.next().column, column + 1);
+        }
+
+        let error = scanner.next();
+        assert_eq!((error.token_type, error.line, error.column), (ScannerError, 2, 5));
+        assert_eq!(scanner.next().column, 8);
+    }
+
+    #[test]
+    fn unicode_source() {
+        let source = "let naïve = \"żółw 🐢\"; €";
+        let mut scanner = Scanner::new(source);
+
+        match_full_token(&mut scanner, TokenLet, "let", 1);
+        match_full_token(&mut scanner, TokenIdentifier, "naïve", 1);
+        match_token(&mut scanner, TokenEqual, 1);
+        match_full_token(&mut scanner, TokenString, "\"żółw 🐢\"", 1);
+        match_token(&mut scanner, TokenSemicolon, 1);
+
+        let error = scanner.next();
+        assert_eq!(error.token_type, ScannerError);
+        assert_eq!((error.column, &source[error.span.start..error.span.end]), (23, "€"));
+        assert_eq!(scanner.next().token_type, EOF);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let source = r#""tab\tnew\nline \\ \"quoted\" \u{1F600}\u{e9}""#;
+        let mut scanner = Scanner::new(source);
+
+        match_full_token(&mut scanner, TokenString, source, 1);
+        assert_eq!(string_value(source), "tab\tnew\nline \\ \"quoted\" 😀é");
+        assert_eq!(scanner.next().token_type, EOF);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        let cases = [
+            (r#""a \q b""#, "Invalid escape sequence", "\\q"),
+            (r#""\u1F600""#, "Expected '{' after \\u", "\\u"),
+            (r#""\u{1F600""#, "Expected '}' after unicode escape", "\\u{1F600"),
+            (r#""\u{}""#, "Unicode escape must have 1 to 6 hex digits", "\\u{}"),
+            (r#""\u{1234567}""#, "Unicode escape must have 1 to 6 hex digits", "\\u{1234567}"),
+            (r#""\u{D800}""#, "Invalid unicode code point", "\\u{D800}"),
+            (r#""é\x" "\n""#, "Invalid escape sequence", "\\x"),
+        ];
+
+        for (source, message, escape) in cases {
+            let mut scanner = Scanner::new(source);
+            let error = scanner.next();
+            assert_eq!(error.token_type, ScannerError, "{}", source);
+            assert_eq!(error.string, message, "{}", source);
+            assert_eq!(&source[error.span.start..error.span.end], escape, "{}", source);
+        }
+    }
+
+    #[test]
+    fn invalid_escape_position() {
+        let source = "\"first\nsecond \\z\" ok";
+        let mut scanner = Scanner::new(source);
+
+        let error = scanner.next();
+        assert_eq!((error.token_type, error.line, error.column), (ScannerError, 2, 8));
+        match_full_token(&mut scanner, TokenIdentifier, "ok", 2);
+    }
 }
\ No newline at end of file
@@ -1,147 +1,767 @@
+pub mod convert;
+pub mod error;
 pub mod value;
+mod natives;
 #[cfg(test)]
 mod tests;
 
-use crate::bytecode::chunk::Chunk;
-use crate::bytecode::codes;
+use crate::bytecode::chunk::{Chunk, Position};
+use crate::bytecode::{codes, verifier};
+use crate::diagnostic::Diagnostic;
 use crate::compiler;
 use std::ops::Neg;
-use crate::vm::value::{Value, FALSE, NIL, TRUE};
+use crate::vm::convert::IntoNative;
+use crate::vm::error::{ErrorKind, InterpretError, RuntimeError, TraceFrame};
+use crate::vm::value::{Closure, Function, Native, NativeFn, Obj, Upvalue, Value, FALSE, NIL, TRUE};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub fn interpret(source: String, repl: bool) -> Result<Value, String> {
-    let chunk = compiler::compile(source, repl).or(Err("Compilation failed"))?;
-    run(&chunk)
+/// Maximum depth of nested calls before reporting a stack overflow
+const FRAMES_MAX: usize = 256;
+/// Maximum depth of calls back into the script from natives, as each of them also takes up space on the Rust stack
+const NESTED_RUNS_MAX: usize = 64;
+
+/// Interprets the source with a fresh [Vm]
+pub fn interpret(source: String, repl: bool) -> Result<Value, InterpretError> {
+    Vm::new().interpret(source, repl)
+}
+
+/// Runs the chunk with a fresh [Vm]
+pub fn run(chunk: &Chunk) -> Result<Value, RuntimeError> {
+    Vm::new().run(chunk)
+}
+
+/// Holds the state that outlives a single chunk, such as the globals seen by consecutive REPL lines
+pub struct Vm {
+    globals: HashMap<String, Value>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    /// Upvalues still pointing into the stack, which are closed when their slot is popped
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// How many runs are executing at once, which is more than one while natives call back into the script
+    nested_runs: usize,
+    /// Instructions left to execute, or `None` for no limit
+    fuel: Option<u64>,
+    /// Set when the fuel ran out in the innermost run, as opposed to in a run that a native called into
+    fuel_exhausted: bool,
+    /// The run that ran out of fuel, kept on the stack so that it can be resumed
+    suspended: Option<Suspended>,
+    interrupted: Arc<AtomicBool>,
+}
+
+/// Where a run that ran out of fuel started, so that its frames can be resumed or unwound
+struct Suspended {
+    base_frames: usize,
+    slots: usize,
+}
+
+/// Stops a [Vm] from another thread
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    pc: usize,
+    /// Index of the stack slot that the frame's local slot 0 refers to
+    slots: usize,
 }
 
-#[allow(unused_assignments)]
-pub fn run(chunk: &Chunk) -> Result<Value, String> {
-    let instructions = &chunk.code;
-    if instructions.is_empty() {
-        return Ok(Value::Nil);
-    }
-    
-    #[allow(unused)]
-    let mut pc: usize = 0; // Performance note: This would likely be faster as a raw (unsafe) pointer
-    let mut stack: Vec<Value> = Vec::new();
-
-    macro_rules! read_byte {
-        () => {{
-            let byte = instructions[pc];
-            pc += 1;
-            byte
-        }};
-    }
-
-    #[rustfmt::skip]
-    macro_rules! read_f64 {
-        () => {
-            f64::from_be_bytes([
-                read_byte!(), read_byte!(), read_byte!(), read_byte!(),
-                read_byte!(), read_byte!(), read_byte!(), read_byte!()
-            ])
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            globals: HashMap::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            nested_runs: 0,
+            fuel: None,
+            fuel_exhausted: false,
+            suspended: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+        };
+
+        natives::define_builtins(&mut vm);
+        vm
+    }
+
+    /// Binds a Rust function to a global, which fails to be called with any other number of arguments than `arity`
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        self.define_native_with_vm(name, arity, move |_, arguments| {
+            function(arguments).map_err(|message| RuntimeError::new(ErrorKind::Native, message))
+        });
+    }
+
+    /// Binds a Rust closure to a global like [Vm::define_native], handing it the VM so that it can call back into the
+    /// script with [Vm::call_value]. Errors raised by those calls can be returned as they are.
+    pub fn define_native_with_vm(
+        &mut self,
+        name: &str,
+        arity: u8,
+        function: impl Fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let native = Native { name: name.to_string(), arity, function: Box::new(function) };
+        self.globals.insert(name.to_string(), Value::Obj(Obj::Native(Rc::new(native))));
+    }
+
+    /// Binds a Rust closure to a global, converting its arguments and return value with
+    /// [FromValue](convert::FromValue) and [IntoValue](convert::IntoValue).
+    ///
+    /// The arity is that of the closure, and an argument of the wrong type is reported as a native error.
+    /// The closure may also return a 64 bit integer, which is reported as a native error if it isn't exactly a number.
+    pub fn bind_native<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        fn arity<Args, F: IntoNative<Args>>(_: &F) -> u8 {
+            F::ARITY
+        }
+
+        let native = Native { name: name.to_string(), arity: arity(&function), function: function.into_native(name) };
+        self.globals.insert(name.to_string(), Value::Obj(Obj::Native(Rc::new(native))));
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    /// Defines the global, or overwrites it if it already exists
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// Compiles and runs the source, returning the value of a trailing expression without a semicolon
+    pub fn eval(&mut self, source: &str) -> Result<Value, InterpretError> {
+        let chunk = compiler::compile(source, true)
+            .map_err(|diagnostics| InterpretError::Compile { diagnostics, source: source.to_string() })?;
+        Ok(self.run(&chunk)?)
+    }
+
+    /// Compiles the source the way [Vm::eval] does, so that the chunk can be run any number of times
+    pub fn compile(source: &str) -> Result<Chunk, Vec<Diagnostic>> {
+        compiler::compile(source, true)
+    }
+
+    pub fn interpret(&mut self, source: String, repl: bool) -> Result<Value, InterpretError> {
+        let chunk = compiler::compile(&source, repl).map_err(|diagnostics| InterpretError::Compile { diagnostics, source })?;
+        Ok(self.run(&chunk)?)
+    }
+
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Value, RuntimeError> {
+        verifier::verify(chunk).map_err(|message| RuntimeError::new(ErrorKind::InvalidBytecode, message))?;
+        self.run_unverified(chunk)
+    }
+
+    /// Limits how many instructions may be executed, or lifts the limit with `None`.
+    ///
+    /// Running out returns an [ErrorKind::OutOfFuel] error, after which the run can be continued with [Vm::resume].
+    /// Fuel that runs out inside a call from a native unwinds the native's call like any other error.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Continues the run that last ran out of fuel, from the instruction it stopped at.
+    /// Starting any other run discards a run that is waiting to be resumed.
+    pub fn resume(&mut self) -> Result<Value, RuntimeError> {
+        let Some(Suspended { base_frames, slots }) = self.suspended.take() else {
+            return Err(RuntimeError::new(ErrorKind::Internal, "There is no run to resume"));
         };
+        self.finish_run(base_frames, slots)
     }
 
-    macro_rules! binary_op {
-        ($operator:tt, $operation_name:literal) => {{
-            let right = peek(&stack, 0).expect("Stack is empty");
-            let left = peek(&stack, 1).expect("Stack only had one element");
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Returns a handle that can stop this VM from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(Arc::clone(&self.interrupted))
+    }
 
-            let result = if let (Value::Number(left), Value::Number(right)) = (left, right) {
-                left $operator right
+    /// Calls the function held by the global, such as one declared with `fun` by a script that already ran
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        let Some(callee) = self.globals.get(name).cloned() else {
+            return Err(RuntimeError::new(ErrorKind::UndefinedVariable, format!("Undefined variable '{}'", name)));
+        };
+        self.call_value(&callee, arguments)
+    }
+
+    /// Calls a function or native value. This may happen while a script is running, such as from within a native,
+    /// in which case the call runs on top of the script's frames and returns back to the native.
+    pub fn call_value(&mut self, callee: &Value, arguments: &[Value]) -> Result<Value, RuntimeError> {
+        self.discard_suspended();
+        let closure = match callee {
+            Value::Obj(Obj::Closure(closure)) => Rc::clone(closure),
+            Value::Obj(Obj::Native(native)) => {
+                let native = Rc::clone(native);
+                if arguments.len() != native.arity as usize {
+                    let message = format!("{} expected {} arguments but got {}", native.name, native.arity, arguments.len());
+                    return Err(RuntimeError::new(ErrorKind::Arity, message));
+                }
+                if self.nested_runs == NESTED_RUNS_MAX {
+                    return Err(RuntimeError::new(ErrorKind::StackOverflow, "Stack overflow"));
+                }
+
+                // Counted as a run, so that fuel running out in a script the native calls unwinds instead of suspending
+                self.nested_runs += 1;
+                let result = (native.function)(self, arguments);
+                self.nested_runs -= 1;
+
+                // A native called from within another native resumes its caller's frame, which saved its pc
+                let pc = self.frames.last().map_or(0, |frame| frame.pc);
+                return result.map_err(|error| self.native_error(&native, error, pc));
+            }
+            other => return Err(RuntimeError::new(ErrorKind::Type, format!("Can only call functions, got {}", other))),
+        };
+
+        if arguments.len() != closure.function.arity as usize {
+            let message = format!("{} expected {} arguments but got {}", closure.function, closure.function.arity, arguments.len());
+            return Err(RuntimeError::new(ErrorKind::Arity, message));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(RuntimeError::new(ErrorKind::StackOverflow, "Stack overflow"));
+        }
+
+        let slots = self.stack.len();
+        self.stack.push(callee.clone());
+        self.stack.extend_from_slice(arguments);
+        self.run_frame(closure, slots)
+    }
+
+    /// Runs the chunk without verifying it first, so broken bytecode has to be caught while running
+    fn run_unverified(&mut self, chunk: &Chunk) -> Result<Value, RuntimeError> {
+        self.discard_suspended();
+        let script = Rc::new(Function {
+            name: "script".to_string(),
+            arity: 0,
+            upvalue_count: 0,
+            chunk: chunk.clone(),
+        });
+        let script = Rc::new(Closure { function: script, upvalues: Vec::new() });
+        self.run_frame(script, self.stack.len())
+    }
+
+    /// Runs the closure in a new frame on top of any frames that are already running, until it returns.
+    /// `slots` is where the closure's stack slots start.
+    fn run_frame(&mut self, closure: Rc<Closure>, slots: usize) -> Result<Value, RuntimeError> {
+        if self.nested_runs == NESTED_RUNS_MAX {
+            self.stack.truncate(slots);
+            return Err(RuntimeError::new(ErrorKind::StackOverflow, "Stack overflow"));
+        }
+        let base_frames = self.frames.len();
+        self.frames.push(CallFrame { closure, pc: 0, slots });
+        self.finish_run(base_frames, slots)
+    }
+
+    /// Executes the run that started with `base_frames` frames until it returns. On an error, its frames are unwound,
+    /// unless it ran out of fuel and isn't nested in another run, in which case it is suspended.
+    fn finish_run(&mut self, base_frames: usize, slots: usize) -> Result<Value, RuntimeError> {
+        self.nested_runs += 1;
+        let result = self.execute(base_frames);
+        self.nested_runs -= 1;
+
+        if let Err(error) = &result {
+            if error.kind == ErrorKind::OutOfFuel && self.fuel_exhausted && self.nested_runs == 0 {
+                self.suspended = Some(Suspended { base_frames, slots });
             } else {
-                return runtime_error(pc, &chunk, format!("Cannot perform {} between {} and {}", $operation_name, left, right));
-            };
+                self.unwind(base_frames, slots);
+            }
+            self.fuel_exhausted = false;
+        }
+        result
+    }
 
-            stack.pop().unwrap();
-            stack.pop().unwrap();
-            stack.push(Value::Number(result));
-        }}
+    /// Unwinds a suspended run before a new top-level run starts, so the new run's slots start above the script's
+    fn discard_suspended(&mut self) {
+        if self.nested_runs == 0 && let Some(Suspended { base_frames, slots }) = self.suspended.take() {
+            self.unwind(base_frames, slots);
+        }
+    }
+
+    /// Drops the frames of a run that won't be continued, and the stack slots above `slots`
+    fn unwind(&mut self, base_frames: usize, slots: usize) {
+        self.close_upvalues(slots);
+        self.stack.truncate(slots);
+        self.frames.truncate(base_frames);
     }
 
-    loop {
-        let instruction: u8 = read_byte!();
+    /// Runs the topmost call frame until it returns back down to `base_frames` frames
+    fn execute(&mut self, base_frames: usize) -> Result<Value, RuntimeError> {
+        let Some(frame) = self.frames.last() else {
+            return Err(RuntimeError::new(ErrorKind::Internal, "No call frame to execute"));
+        };
+        let mut closure = Rc::clone(&frame.closure);
+        let mut pc: usize = frame.pc; // Performance note: This would likely be faster as a raw (unsafe) pointer
+        let mut slots: usize = frame.slots;
+
+        macro_rules! read_byte {
+            () => {{
+                let Some(&byte) = closure.function.chunk.code.get(pc) else {
+                    error!(Internal, "Instruction at offset {} is truncated", pc);
+                };
+                pc += 1;
+                byte
+            }};
+        }
+
+        #[rustfmt::skip]
+        macro_rules! read_f64 {
+            () => {
+                f64::from_be_bytes([
+                    read_byte!(), read_byte!(), read_byte!(), read_byte!(),
+                    read_byte!(), read_byte!(), read_byte!(), read_byte!()
+                ])
+            };
+        }
+
+        macro_rules! read_u16 {
+            () => {
+                u16::from_be_bytes([read_byte!(), read_byte!()])
+            };
+        }
+
+        macro_rules! read_u24 {
+            () => {
+                u32::from_be_bytes([0, read_byte!(), read_byte!(), read_byte!()]) as usize
+            };
+        }
+
+        /// Reads a constant index, which is 24 bits for the long variant of an instruction and a byte otherwise
+        macro_rules! read_index {
+            ($long:expr) => {
+                if $long { read_u24!() } else { read_byte!() as usize }
+            };
+        }
+
+        macro_rules! error {
+            ($kind:ident, $($arg:tt)*) => {
+                return Err(self.runtime_error(ErrorKind::$kind, pc, format!($($arg)*)))
+            };
+        }
+
+        macro_rules! read_constant {
+            ($index:expr) => {{
+                let index = $index;
+                match closure.function.chunk.constants().get(index) {
+                    Some(constant) => constant.clone(),
+                    None => error!(Internal, "Constant {} is out of bounds", index),
+                }
+            }};
+        }
+
+        macro_rules! read_name {
+            ($long:expr) => {{
+                match read_constant!(read_index!($long)) {
+                    Value::Obj(Obj::StringObj { value }) => value,
+                    other => error!(Internal, "Expected variable name constant, got {}", other),
+                }
+            }};
+        }
 
-        match instruction {
-            codes::OP_F64 => stack.push(Value::Number(read_f64!())),
-            codes::OP_NIL => stack.push(NIL),
-            codes::OP_TRUE => stack.push(TRUE),
-            codes::OP_FALSE => stack.push(FALSE),
-            codes::OP_CONTANT => {
-                let constant = chunk.load_constant(read_byte!());
-                stack.push(constant);
-            },
+        macro_rules! pop {
+            () => {
+                match self.stack.pop() {
+                    Some(value) => value,
+                    None => error!(Internal, "Stack is empty"),
+                }
+            };
+        }
 
-            codes::OP_ADD => {
-                let right = peek(&stack, 0).expect("Stack is empty");
-                let left = peek(&stack, 1).expect("Stack only had one element");
+        macro_rules! peek {
+            ($distance:expr) => {
+                match peek(&self.stack, $distance) {
+                    Some(value) => value,
+                    None => error!(Internal, "Stack has fewer than {} values", $distance + 1),
+                }
+            };
+        }
+
+        /// Index of a stack slot that must already hold a value
+        macro_rules! slot {
+            ($slot:expr) => {{
+                let slot = $slot;
+                if slot >= self.stack.len() {
+                    error!(Internal, "Stack slot {} is out of bounds", slot);
+                }
+                slot
+            }};
+        }
+
+        macro_rules! upvalue {
+            ($index:expr) => {{
+                let index = $index;
+                match closure.upvalues.get(index) {
+                    Some(upvalue) => upvalue,
+                    None => error!(Internal, "Upvalue {} is out of bounds", index),
+                }
+            }};
+        }
+
+        macro_rules! binary_op {
+            ($operator:tt, $operation_name:literal) => {{
+                let right = peek!(0);
+                let left = peek!(1);
 
                 let result = if let (Value::Number(left), Value::Number(right)) = (left, right) {
-                    Value::Number(left + right)
-                } else if left.is_string() || right.is_string() {
-                    Value::from(format!("{}{}", left, right))
+                    left $operator right
                 } else {
-                    return runtime_error(pc, &chunk, format!("Cannot perform addition between {} and {}", left, right));
+                    error!(Type, "Cannot perform {} between {} and {}", $operation_name, left, right);
                 };
 
-                stack.pop().unwrap();
-                stack.pop().unwrap();
-                stack.push(result);
-            },
-            codes::OP_SUBTRACT => binary_op!(-, "subtraction"),
-            codes::OP_DIVIDE => binary_op!(/, "divide"),
-            codes::OP_MULTIPLY => binary_op!(*, "multiplication"),
-            codes::OP_NEGATE => {
-                let value = stack.last_mut().expect("Stack is empty");
-                match value {
-                    Value::Number(number) => {
-                        *number = number.neg()
-                    }
-                    _ => {
-                        return runtime_error(pc, &chunk, format!("Attempt to negate {}", value.to_string()))
-                    },
+                self.stack.truncate(self.stack.len() - 2);
+                self.stack.push(Value::Number(result));
+            }}
+        }
+
+        macro_rules! comparison_op {
+            ($operator:tt, $operation_name:literal) => {{
+                let right = peek!(0);
+                let left = peek!(1);
+
+                let result = match (left, right) {
+                    (Value::Number(left), Value::Number(right)) => left $operator right,
+                    (Value::Obj(Obj::StringObj { value: left }), Value::Obj(Obj::StringObj { value: right })) => left $operator right,
+                    _ => error!(Type, "Cannot perform {} between {} and {}", $operation_name, left, right),
                 };
-            }
 
-            codes::OP_NOT => {
-                let value = stack.pop().expect("Stack is empty");
-                match value {
-                    Value::Bool(bool) => stack.push(Value::Bool(!bool)),
-                    _ => return runtime_error(pc, &chunk, format!("Attempt to negate {}", value.to_string()))
+                self.stack.truncate(self.stack.len() - 2);
+                self.stack.push(Value::Bool(result));
+            }}
+        }
+
+        loop {
+            if let Some(fuel) = self.fuel {
+                if fuel == 0 {
+                    // Resuming starts again from this instruction
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.pc = pc;
+                    }
+                    self.fuel_exhausted = true;
+                    return Err(self.runtime_error(ErrorKind::OutOfFuel, pc + 1, "Ran out of fuel".to_string()));
                 }
-            },
-            codes::OP_EQUALS => {
-                let (left, right) = pop2(&mut stack);
-                stack.push(Value::Bool(left == right));
+                self.fuel = Some(fuel - 1);
             }
-            codes::OP_NOT_EQUALS => {
-                let (left, right) = pop2(&mut stack);
-                stack.push(Value::Bool(left != right));
+            if self.interrupted.load(Ordering::Relaxed) {
+                self.interrupted.store(false, Ordering::Relaxed);
+                error!(Interrupted, "Interrupted");
             }
 
-            codes::OP_POP => { stack.pop().expect("Stack is empty"); },
-            codes::OP_RETURN => return Ok(stack.pop().expect("Stack is empty")),
-            _ => panic!("Unexpected opcode: {:04x}", instruction),
+            let instruction: u8 = if pc < closure.function.chunk.code.len() {
+                read_byte!()
+            } else {
+                // Running off the end of a chunk returns nil
+                self.stack.push(NIL);
+                codes::OP_RETURN
+            };
+
+            match instruction {
+                codes::OP_F64 => self.stack.push(Value::Number(read_f64!())),
+                codes::OP_NIL => self.stack.push(NIL),
+                codes::OP_TRUE => self.stack.push(TRUE),
+                codes::OP_FALSE => self.stack.push(FALSE),
+                codes::OP_CONTANT => {
+                    let constant = read_constant!(read_byte!() as usize);
+                    self.stack.push(constant);
+                },
+                codes::OP_CONSTANT_LONG => {
+                    let constant = read_constant!(read_u24!());
+                    self.stack.push(constant);
+                }
+
+                codes::OP_ADD => {
+                    let right = peek!(0);
+                    let left = peek!(1);
+
+                    let result = if let (Value::Number(left), Value::Number(right)) = (left, right) {
+                        Value::Number(left + right)
+                    } else if left.is_string() || right.is_string() {
+                        Value::from(format!("{}{}", left, right))
+                    } else {
+                        error!(Type, "Cannot perform addition between {} and {}", left, right);
+                    };
+
+                    self.stack.truncate(self.stack.len() - 2);
+                    self.stack.push(result);
+                },
+                codes::OP_SUBTRACT => binary_op!(-, "subtraction"),
+                codes::OP_DIVIDE => binary_op!(/, "divide"),
+                codes::OP_MULTIPLY => binary_op!(*, "multiplication"),
+                codes::OP_NEGATE => {
+                    match self.stack.last_mut() {
+                        Some(Value::Number(number)) => {
+                            *number = number.neg()
+                        }
+                        None => error!(Internal, "Stack is empty"),
+                        Some(value) => {
+                            let value = value.to_string();
+                            error!(Type, "Attempt to negate {}", value)
+                        },
+                    };
+                }
+
+                codes::OP_NOT => {
+                    let value = pop!();
+                    match value {
+                        Value::Bool(bool) => self.stack.push(Value::Bool(!bool)),
+                        _ => error!(Type, "Attempt to negate {}", value)
+                    }
+                },
+                codes::OP_EQUALS => {
+                    let right = pop!();
+                    let left = pop!();
+                    self.stack.push(Value::Bool(left == right));
+                }
+                codes::OP_NOT_EQUALS => {
+                    let right = pop!();
+                    let left = pop!();
+                    self.stack.push(Value::Bool(left != right));
+                }
+                codes::OP_LESS_THAN => comparison_op!(<, "less than comparison"),
+                codes::OP_LESS_THAN_OR_EQUALS => comparison_op!(<=, "less than or equals comparison"),
+                codes::OP_GREATER_THAN => comparison_op!(>, "greater than comparison"),
+                codes::OP_GREATER_THAN_OR_EQUALS => comparison_op!(>=, "greater than or equals comparison"),
+
+                codes::OP_POP => { pop!(); },
+                codes::OP_RETURN => {
+                    let result = pop!();
+                    let Some(frame) = self.frames.pop() else {
+                        error!(Internal, "No call frame to return from");
+                    };
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+
+                    if self.frames.len() <= base_frames {
+                        return Ok(result);
+                    }
+
+                    self.stack.push(result);
+                    let Some(frame) = self.frames.last() else {
+                        error!(Internal, "No call frame to return to");
+                    };
+                    closure = Rc::clone(&frame.closure);
+                    pc = frame.pc;
+                    slots = frame.slots;
+                }
+                codes::OP_CALL => {
+                    let arg_count = read_byte!() as usize;
+                    let callee = match peek!(arg_count) {
+                        Value::Obj(Obj::Closure(callee)) => Rc::clone(callee),
+                        Value::Obj(Obj::Native(native)) => {
+                            let native = Rc::clone(native);
+                            if arg_count != native.arity as usize {
+                                error!(Arity, "{} expected {} arguments but got {}", native.name, native.arity, arg_count);
+                            }
+
+                            // The native may call back into the script, which needs to know where to return to
+                            if let Some(frame) = self.frames.last_mut() {
+                                frame.pc = pc;
+                            }
+                            let arguments = self.stack.split_off(self.stack.len() - arg_count);
+                            pop!();
+
+                            let result = match (native.function)(self, &arguments) {
+                                Ok(result) => result,
+                                Err(error) => return Err(self.native_error(&native, error, pc)),
+                            };
+                            self.stack.push(result);
+                            continue;
+                        }
+                        other => error!(Type, "Can only call functions, got {}", other),
+                    };
+
+                    if arg_count != callee.function.arity as usize {
+                        error!(Arity, "{} expected {} arguments but got {}", callee.function, callee.function.arity, arg_count);
+                    }
+
+                    if self.frames.len() == FRAMES_MAX {
+                        error!(StackOverflow, "Stack overflow");
+                    }
+
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.pc = pc;
+                    }
+                    slots = self.stack.len() - arg_count - 1;
+                    self.frames.push(CallFrame { closure: Rc::clone(&callee), pc: 0, slots });
+                    closure = callee;
+                    pc = 0;
+                }
+                codes::OP_CLOSURE | codes::OP_CLOSURE_LONG => {
+                    let function = match read_constant!(read_index!(instruction == codes::OP_CLOSURE_LONG)) {
+                        Value::Obj(Obj::Function(function)) => function,
+                        other => error!(Internal, "Expected function constant, got {}", other),
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count as usize);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = read_byte!() == 1;
+                        let index = read_byte!() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(slot!(slots + index))
+                        } else {
+                            Rc::clone(upvalue!(index))
+                        });
+                    }
+
+                    self.stack.push(Value::Obj(Obj::Closure(Rc::new(Closure { function, upvalues }))));
+                }
+                codes::OP_GET_UPVALUE => {
+                    let index = read_byte!() as usize;
+                    let value = match &*upvalue!(index).borrow() {
+                        Upvalue::Open(slot) => self.stack[slot!(*slot)].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                codes::OP_SET_UPVALUE => {
+                    let index = read_byte!() as usize;
+                    let value = peek!(0).clone();
+                    match &mut *upvalue!(index).borrow_mut() {
+                        Upvalue::Open(slot) => {
+                            let slot = slot!(*slot);
+                            self.stack[slot] = value
+                        }
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                codes::OP_CLOSE_UPVALUE => {
+                    let top = slot!(self.stack.len().wrapping_sub(1));
+                    self.close_upvalues(top);
+                    self.stack.pop();
+                }
+
+                codes::OP_GET_LOCAL => {
+                    let slot = read_byte!() as usize;
+                    self.stack.push(self.stack[slot!(slots + slot)].clone());
+                }
+                codes::OP_SET_LOCAL => {
+                    let slot = read_byte!() as usize;
+                    let value = peek!(0).clone();
+                    let slot = slot!(slots + slot);
+                    self.stack[slot] = value;
+                }
+                codes::OP_GET_GLOBAL | codes::OP_GET_GLOBAL_LONG => {
+                    let name = read_name!(instruction == codes::OP_GET_GLOBAL_LONG);
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => error!(UndefinedVariable, "Undefined variable '{}'", name),
+                    }
+                }
+                codes::OP_SET_GLOBAL | codes::OP_SET_GLOBAL_LONG => {
+                    let name = read_name!(instruction == codes::OP_SET_GLOBAL_LONG);
+                    let value = peek!(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => error!(UndefinedVariable, "Undefined variable '{}'", name),
+                    }
+                }
+                codes::OP_DEFINE_GLOBAL | codes::OP_DEFINE_GLOBAL_LONG => {
+                    let name = read_name!(instruction == codes::OP_DEFINE_GLOBAL_LONG);
+                    let value = pop!();
+                    self.globals.insert(name, value);
+                }
+
+                codes::OP_JUMP => {
+                    let offset = read_u16!() as usize;
+                    pc += offset;
+                }
+                codes::OP_LOOP => {
+                    let offset = read_u16!() as usize;
+                    pc = match pc.checked_sub(offset) {
+                        Some(target) => target,
+                        None => error!(Internal, "Loop jumps before the start of the code"),
+                    };
+                }
+                codes::OP_JUMP_IF_FALSE => {
+                    let offset = read_u16!() as usize;
+                    match peek!(0) {
+                        Value::Bool(false) => pc += offset,
+                        Value::Bool(true) => {}
+                        other => error!(Type, "Expected condition to be a bool, got {}", other),
+                    }
+                }
+                _ => error!(Internal, "Unexpected opcode: {:#04x}", instruction),
+            }
         }
     }
 }
 
-fn peek(stack: &Vec<Value>, offset_from_end: usize) -> Option<&Value> {
-    let len = stack.len();
-    stack.get(len - 1 - offset_from_end)
-}
+impl Vm {
+    /// Builds an error with a trace of every call on the stack, where `pc` belongs to the innermost one
+    fn runtime_error(&self, kind: ErrorKind, pc: usize, message: String) -> RuntimeError {
+        let innermost = self.frames.len().saturating_sub(1);
+        let trace = self.frames.iter()
+            .enumerate()
+            .rev()
+            .map(|(index, frame)| {
+                // The innermost frame keeps its pc in a local while it runs, the others saved it when calling
+                let pc = if index == innermost { pc } else { frame.pc };
+                let chunk = &frame.closure.function.chunk;
+                let position = if pc == 0 || pc > chunk.code.len() { Position::default() } else { chunk.position_at(pc - 1) };
+                TraceFrame { function: frame.closure.function.name.clone(), position }
+            })
+            .collect();
+
+        RuntimeError { kind, message, trace }
+    }
+
+    /// Adds the native and the calls on the stack to an error returned by the native. When the error came from a call
+    /// back into the script, it already holds the calls on the stack, and the native is inserted where it made that call.
+    fn native_error(&self, native: &Native, error: RuntimeError, pc: usize) -> RuntimeError {
+        let mut error = if error.trace.is_empty() { self.runtime_error(error.kind, pc, error.message) } else { error };
+        let index = error.trace.len().saturating_sub(self.frames.len());
+        error.trace.insert(index, TraceFrame { function: native.name.clone(), position: Position::default() });
+        error
+    }
 
-fn pop2(stack: &mut Vec<Value>) -> (Value, Value) {
-    let right = stack.pop().expect("Stack is empty");
-    let left = stack.pop().expect("Stack only had one element");
-    (left, right)
+    /// Returns the upvalue for the stack slot, reusing it if another closure already captured the slot
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self.open_upvalues.iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open_slot) if open_slot == slot));
+
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves the variables of every open upvalue at or above `from` off the stack
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    // A slot past the end of the stack can only come from broken bytecode, which was already reported
+                    *upvalue = Upvalue::Closed(stack.get(slot).cloned().unwrap_or(NIL));
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
 }
 
-fn runtime_error<T>(pc: usize, chunk: &Chunk, error: String) -> Result<T, String> {
-    let line = chunk.get_line(pc - 1);
-    if line == 0 {
-        Err(error)
-    } else {
-        Err(format!("[Line {}] {}", line, error))
+impl InterruptHandle {
+    /// Stops the VM with an [ErrorKind::Interrupted] error before its next instruction.
+    /// If the VM isn't running, its next run is stopped instead.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
     }
-}
\ No newline at end of file
+}
+
+fn peek(stack: &[Value], offset_from_end: usize) -> Option<&Value> {
+    let index = stack.len().checked_sub(offset_from_end.checked_add(1)?)?;
+    stack.get(index)
+}
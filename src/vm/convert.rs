@@ -0,0 +1,273 @@
+//! Conversions between fops values and Rust types, used to bind plain Rust closures as natives.
+
+use crate::vm::error::{ErrorKind, RuntimeError};
+use crate::vm::value::{NativeBody, Obj, Value};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+/// A value couldn't be converted to the Rust type that was asked for
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+    /// What the value should have been, such as `string` or `number or nil`
+    pub expected: String,
+    /// The type of the value that was found instead, or the value itself if it had the right type but didn't fit
+    pub found: String,
+}
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, ConversionError>;
+}
+
+/// What a bound closure may return: any [IntoValue] type, or a [Result] whose error is reported as a native error
+pub trait IntoNativeResult {
+    fn into_native_result(self) -> Result<Value, String>;
+}
+
+/// A Rust closure that can be bound as a native, taking `Args` as a tuple of [FromValue] arguments
+pub trait IntoNative<Args> {
+    const ARITY: u8;
+
+    /// Wraps the closure so that its arguments are converted, naming `name` when one of them has the wrong type
+    fn into_native(self, name: &str) -> NativeBody;
+}
+
+impl ConversionError {
+    fn new(expected: impl Into<String>, found: &Value) -> Self {
+        Self { expected: expected.into(), found: found.type_name().to_string() }
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Number(number) => Ok(*number),
+            other => Err(ConversionError::new("number", other)),
+        }
+    }
+}
+
+/// Integers are numbers without a fractional part that fit in the integer type
+macro_rules! integer_conversions {
+    ($($integer:ty),*) => {$(
+        impl FromValue for $integer {
+            fn from_value(value: &Value) -> Result<Self, ConversionError> {
+                let number = f64::from_value(value)?;
+                // MAX + 1 is a power of two and exact as an f64, while MAX itself rounds up to it for 64 bit integers
+                let limit = <$integer>::MAX as f64 + 1.0;
+                if number.fract() != 0.0 || number < <$integer>::MIN as f64 || number >= limit {
+                    return Err(ConversionError { expected: stringify!($integer).to_string(), found: number.to_string() });
+                }
+                Ok(number as $integer)
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Only integers that every value of is exactly a number convert into one
+macro_rules! exact_integer_values {
+    ($($integer:ty),*) => {$(
+        impl IntoValue for $integer {
+            fn into_value(self) -> Value {
+                Value::Number(self as f64)
+            }
+        }
+    )*};
+}
+
+exact_integer_values!(i8, i16, i32, u8, u16, u32);
+
+/// Every integer up to this magnitude is exactly a number
+const MAX_EXACT_INTEGER: u128 = 1 << 53;
+
+/// Above 2^53 not every integer is a number, so wider integers may only be returned by natives,
+/// which report the ones that aren't as an error rather than rounding them
+macro_rules! wide_integer_results {
+    ($($integer:ty),*) => {$(
+        impl IntoNativeResult for $integer {
+            fn into_native_result(self) -> Result<Value, String> {
+                if (self as i128).unsigned_abs() > MAX_EXACT_INTEGER {
+                    return Err(format!("{} is too large to be represented exactly as a number", self));
+                }
+                Ok(Value::Number(self as f64))
+            }
+        }
+
+        impl<E: Display> IntoNativeResult for Result<$integer, E> {
+            fn into_native_result(self) -> Result<Value, String> {
+                self.map_err(|error| error.to_string())?.into_native_result()
+            }
+        }
+    )*};
+}
+
+wide_integer_results!(i64, isize, u64, usize);
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Bool(bool) => Ok(*bool),
+            other => Err(ConversionError::new("bool", other)),
+        }
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(()),
+            other => Err(ConversionError::new("nil", other)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Obj(Obj::StringObj { value }) => Ok(value.clone()),
+            other => Err(ConversionError::new("string", other)),
+        }
+    }
+}
+
+/// `None` is nil
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Nil, IntoValue::into_value)
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        match value {
+            Value::Nil => Ok(None),
+            other => T::from_value(other)
+                .map(Some)
+                .map_err(|error| ConversionError { expected: format!("{} or nil", error.expected), ..error }),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::Obj(Obj::List(Rc::new(self.into_iter().map(IntoValue::into_value).collect())))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        let Value::Obj(Obj::List(elements)) = value else {
+            return Err(ConversionError::new("list", value));
+        };
+        elements.iter()
+            .enumerate()
+            .map(|(index, element)| T::from_value(element).map_err(|error| ConversionError {
+                expected: format!("list of {}", error.expected),
+                found: format!("{} at index {}", error.found, index),
+            }))
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+    fn into_native_result(self) -> Result<Value, String> {
+        Ok(self.into_value())
+    }
+}
+
+impl<T: IntoValue, E: Display> IntoNativeResult for Result<T, E> {
+    fn into_native_result(self) -> Result<Value, String> {
+        self.map(IntoValue::into_value).map_err(|error| error.to_string())
+    }
+}
+
+macro_rules! closure_natives {
+    ($($argument:ident),*) => {
+        impl<F, R, $($argument),*> IntoNative<($($argument,)*)> for F
+        where
+            F: Fn($($argument),*) -> R + 'static,
+            R: IntoNativeResult,
+            $($argument: FromValue,)*
+        {
+            const ARITY: u8 = <[&str]>::len(&[$(stringify!($argument)),*]) as u8;
+
+            #[allow(non_snake_case, unused_variables, unused_mut, unused_assignments)]
+            fn into_native(self, name: &str) -> NativeBody {
+                let name = name.to_string();
+                Box::new(move |_, arguments| {
+                    let mut index = 0;
+                    $(
+                        let $argument = $argument::from_value(&arguments[index]).map_err(|error| {
+                            RuntimeError::new(ErrorKind::Native, format!("Argument {} of {}: {}", index + 1, name, error))
+                        })?;
+                        index += 1;
+                    )*
+                    self($($argument),*).into_native_result().map_err(|message| RuntimeError::new(ErrorKind::Native, message))
+                })
+            }
+        }
+    };
+}
+
+closure_natives!();
+closure_natives!(A);
+closure_natives!(A, B);
+closure_natives!(A, B, C);
+closure_natives!(A, B, C, D);
+closure_natives!(A, B, C, D, E);
+closure_natives!(A, B, C, D, E, G);
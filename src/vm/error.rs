@@ -0,0 +1,116 @@
+use crate::bytecode::chunk::Position;
+use crate::diagnostic::{self, Diagnostic};
+use std::fmt::{Display, Formatter, Write};
+
+/// An error raised while running a chunk, along with the calls that led to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The innermost call is first and the top-level script is last
+    pub trace: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An operation was applied to values of the wrong type
+    Type,
+    UndefinedVariable,
+    /// A function was called with the wrong number of arguments
+    Arity,
+    StackOverflow,
+    /// A native function reported an error
+    Native,
+    /// The fuel set with [Vm::set_fuel](crate::vm::Vm::set_fuel) ran out, which can be resumed with more fuel
+    OutOfFuel,
+    /// The run was stopped through an [InterruptHandle](crate::vm::InterruptHandle)
+    Interrupted,
+    /// The chunk was rejected before it started running
+    InvalidBytecode,
+    /// The VM reached a state that correct bytecode can't produce, such as popping from an empty stack
+    Internal,
+}
+
+/// Where one of the calls on the stack was when the error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub position: Position,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into(), trace: Vec::new() }
+    }
+
+    /// Renders the error with every call on the stack, the most recent call last
+    pub fn traceback(&self) -> String {
+        let mut output = String::new();
+        if !self.trace.is_empty() {
+            writeln!(output, "Traceback (most recent call last):").unwrap();
+        }
+        for frame in self.trace.iter().rev() {
+            if frame.position.line == 0 {
+                writeln!(output, "  in {}", frame.function).unwrap();
+            } else {
+                writeln!(output, "  [Line {}] in {}", frame.position, frame.function).unwrap();
+            }
+        }
+        write!(output, "{}: {}", self.kind, self.message).unwrap();
+        output
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ErrorKind::Type => "Type error",
+            ErrorKind::UndefinedVariable => "Undefined variable",
+            ErrorKind::Arity => "Arity error",
+            ErrorKind::StackOverflow => "Stack overflow",
+            ErrorKind::Native => "Native error",
+            ErrorKind::OutOfFuel => "Out of fuel",
+            ErrorKind::Interrupted => "Interrupted",
+            ErrorKind::InvalidBytecode => "Invalid bytecode",
+            ErrorKind::Internal => "Internal error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Prefixes the message with the position of the innermost call, when it is known
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.trace.first() {
+            Some(frame) if frame.position.line != 0 => write!(f, "[Line {}] {}", frame.position, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Why interpreting source code failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpretError {
+    /// Source code that failed to compile, kept alongside its diagnostics so that they can be rendered
+    Compile { diagnostics: Vec<Diagnostic>, source: String },
+    Runtime(RuntimeError),
+}
+
+impl From<RuntimeError> for InterpretError {
+    fn from(error: RuntimeError) -> Self {
+        InterpretError::Runtime(error)
+    }
+}
+
+impl Display for InterpretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::Compile { diagnostics, source } => write!(f, "{}", diagnostic::render_all(diagnostics, source)),
+            InterpretError::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
@@ -0,0 +1,40 @@
+use crate::vm::value::Value;
+use crate::vm::Vm;
+use std::io;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) fn define_builtins(vm: &mut Vm) {
+    vm.define_native("print", 1, print);
+    vm.define_native("println", 1, println);
+    vm.define_native("clock", 0, clock);
+    vm.define_native("type_of", 1, type_of);
+    vm.define_native("to_string", 1, to_string);
+}
+
+fn print(arguments: &[Value]) -> Result<Value, String> {
+    print!("{}", arguments[0]);
+    io::stdout().flush().map_err(|error| error.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn println(arguments: &[Value]) -> Result<Value, String> {
+    println!("{}", arguments[0]);
+    Ok(Value::Nil)
+}
+
+/// Seconds since the Unix epoch
+fn clock(_: &[Value]) -> Result<Value, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|error| error.to_string())?;
+    Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+fn type_of(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(arguments[0].type_name()))
+}
+
+fn to_string(arguments: &[Value]) -> Result<Value, String> {
+    Ok(Value::from(arguments[0].to_string()))
+}
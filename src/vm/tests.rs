@@ -1,13 +1,20 @@
 mod bools;
+mod convert;
+mod functions;
+mod fuzz;
+mod globals;
+mod jumps;
+mod locals;
+mod loops;
 mod numbers;
 mod strings;
 mod nil;
 
+use crate::vm::error::RuntimeError;
 use crate::vm::value::*;
 
-fn assert_runtime_error(result: Result<Value, String>) {
-    match result {
-        Ok(value) => panic!("Expected runtime error, got {}", value),
-        Err(_) => {}
+fn assert_runtime_error(result: Result<Value, RuntimeError>) {
+    if let Ok(value) = result {
+        panic!("Expected runtime error, got {}", value)
     }
 }
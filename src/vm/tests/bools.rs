@@ -46,4 +46,8 @@ fn illegal_operations() {
     vm_test!(OP_TRUE, OP_TRUE, OP_MULTIPLY => !);
     vm_test!(OP_TRUE, OP_TRUE, OP_DIVIDE => !);
     vm_test!(OP_TRUE, OP_TRUE, OP_NEGATE => !);
+    vm_test!(OP_FALSE, OP_TRUE, OP_LESS_THAN => !);
+    vm_test!(OP_FALSE, OP_TRUE, OP_LESS_THAN_OR_EQUALS => !);
+    vm_test!(OP_TRUE, OP_FALSE, OP_GREATER_THAN => !);
+    vm_test!(OP_TRUE, OP_FALSE, OP_GREATER_THAN_OR_EQUALS => !);
 }
@@ -0,0 +1,63 @@
+use crate::vm::convert::{ConversionError, FromValue, IntoValue};
+use crate::vm::value::Value;
+
+#[test]
+fn round_trips() {
+    assert_eq!(f64::from_value(&1.5.into_value()), Ok(1.5));
+    assert_eq!(i32::from_value(&(-7).into_value()), Ok(-7));
+    assert_eq!(bool::from_value(&true.into_value()), Ok(true));
+    assert_eq!(<()>::from_value(&().into_value()), Ok(()));
+    assert_eq!(String::from_value(&"fops".into_value()), Ok("fops".to_string()));
+    assert_eq!(Option::<f64>::from_value(&None::<f64>.into_value()), Ok(None));
+    assert_eq!(Option::<f64>::from_value(&Some(2.0).into_value()), Ok(Some(2.0)));
+    assert_eq!(Vec::<String>::from_value(&vec!["a", "b"].into_value()), Ok(vec!["a".to_string(), "b".to_string()]));
+    assert_eq!(Vec::<Vec<u8>>::from_value(&vec![vec![1u8], vec![]].into_value()), Ok(vec![vec![1], vec![]]));
+}
+
+#[test]
+fn type_mismatch() {
+    let error = String::from_value(&Value::Number(1.0)).unwrap_err();
+    assert_eq!(error.to_string(), "expected string, got number");
+
+    let error = Option::<bool>::from_value(&Value::from("yes")).unwrap_err();
+    assert_eq!(error.to_string(), "expected bool or nil, got string");
+
+    let error = Vec::<f64>::from_value(&Value::Nil).unwrap_err();
+    assert_eq!(error.to_string(), "expected list, got nil");
+
+    let error = Vec::<f64>::from_value(&vec![Value::Number(1.0), Value::Bool(true)].into_value()).unwrap_err();
+    assert_eq!(error.to_string(), "expected list of number, got bool at index 1");
+}
+
+#[test]
+fn integers_must_fit() {
+    assert_eq!(u8::from_value(&Value::Number(255.0)), Ok(255));
+    assert_eq!(
+        u8::from_value(&Value::Number(256.0)),
+        Err(ConversionError { expected: "u8".to_string(), found: "256".to_string() })
+    );
+    assert_eq!(u32::from_value(&Value::Number(-1.0)).unwrap_err().to_string(), "expected u32, got -1");
+    assert_eq!(i64::from_value(&Value::Number(0.5)).unwrap_err().to_string(), "expected i64, got 0.5");
+    assert_eq!(i64::from_value(&Value::Nil).unwrap_err().to_string(), "expected number, got nil");
+    assert!(u8::from_value(&Value::Number(f64::NAN)).is_err());
+    assert!(i32::from_value(&Value::Number(f64::INFINITY)).is_err());
+}
+
+#[test]
+fn integer_boundaries() {
+    let two_to_the = |power: i32| Value::Number(2f64.powi(power));
+
+    assert_eq!(i64::from_value(&two_to_the(62)), Ok(1 << 62));
+    assert_eq!(i64::from_value(&Value::Number(-(2f64.powi(63)))), Ok(i64::MIN));
+    assert_eq!(i64::from_value(&two_to_the(63)).unwrap_err().expected, "i64");
+    assert!(i64::from_value(&two_to_the(64)).is_err());
+
+    assert_eq!(u64::from_value(&two_to_the(63)), Ok(1 << 63));
+    assert_eq!(u64::from_value(&two_to_the(64)).unwrap_err().expected, "u64");
+    assert!(u64::from_value(&Value::Number(-1.0)).is_err());
+
+    assert_eq!(i32::from_value(&Value::Number(2147483647.0)), Ok(i32::MAX));
+    assert!(i32::from_value(&two_to_the(31)).is_err());
+    assert_eq!(i8::from_value(&Value::Number(-128.0)), Ok(i8::MIN));
+    assert!(i8::from_value(&Value::Number(-129.0)).is_err());
+}
@@ -0,0 +1,128 @@
+use crate::bytecode::chunk::{Chunk, Position};
+use crate::bytecode::codes::*;
+use crate::vm::run;
+use crate::vm::tests::assert_runtime_error;
+use crate::vm::value::{Function, Obj, Value};
+use std::rc::Rc;
+
+/// Writes an OP_CLOSURE for a function without upvalues
+fn write_closure(chunk: &mut Chunk, function: Function) {
+    let index = chunk.add_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+    chunk.write0(OP_CLOSURE);
+    chunk.write0(index as u8);
+}
+
+/// Builds a chunk that calls a function taking two arguments which returns `left - right`
+fn call_subtract(arguments: &[f64]) -> Chunk {
+    let mut body = Chunk::new();
+    body.write0(OP_GET_LOCAL);
+    body.write0(1);
+    body.write0(OP_GET_LOCAL);
+    body.write0(2);
+    body.write0(OP_SUBTRACT);
+    body.write0(OP_RETURN);
+
+    let function = Function { name: "subtract".to_string(), arity: 2, upvalue_count: 0, chunk: body };
+    let mut chunk = Chunk::new();
+    write_closure(&mut chunk, function);
+    arguments.iter().for_each(|argument| chunk.write_f64_0(*argument));
+    chunk.write0(OP_CALL);
+    chunk.write0(arguments.len() as u8);
+    chunk.write0(OP_RETURN);
+    chunk
+}
+
+#[test]
+fn call() {
+    assert_eq!(run(&call_subtract(&[5.0, 3.0])), Ok(Value::Number(2.0)));
+}
+
+#[test]
+fn arity_mismatch() {
+    assert_runtime_error(run(&call_subtract(&[5.0])));
+    assert_runtime_error(run(&call_subtract(&[5.0, 3.0, 1.0])));
+}
+
+#[test]
+fn call_non_function() {
+    let mut chunk = Chunk::new();
+    chunk.write_f64_0(5.0);
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    assert_runtime_error(run(&chunk));
+
+    let mut chunk = Chunk::new();
+    chunk.write_constant(Value::from("f"), Position::default()).unwrap();
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    assert_runtime_error(run(&chunk));
+}
+
+#[test]
+fn implicit_return_at_end_of_function() {
+    let function = Function { name: "empty".to_string(), arity: 0, upvalue_count: 0, chunk: Chunk::new() };
+    let mut chunk = Chunk::new();
+    write_closure(&mut chunk, function);
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    chunk.write0(OP_RETURN);
+    assert_eq!(run(&chunk), Ok(Value::Nil));
+}
+
+#[test]
+fn call_bare_function() {
+    let function = Function { name: "bare".to_string(), arity: 0, upvalue_count: 0, chunk: Chunk::new() };
+    let mut chunk = Chunk::new();
+    chunk.write_constant(Value::Obj(Obj::Function(Rc::new(function))), Position::default()).unwrap();
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    assert_runtime_error(run(&chunk));
+}
+
+/// Builds a closure over a local holding 5.0 which returns its upvalue, then calls it
+#[test]
+fn get_upvalue() {
+    let mut body = Chunk::new();
+    body.write0(OP_GET_UPVALUE);
+    body.write0(0);
+    body.write0(OP_RETURN);
+    let function = Function { name: "inner".to_string(), arity: 0, upvalue_count: 1, chunk: body };
+
+    let mut chunk = Chunk::new();
+    chunk.write_f64_0(5.0);
+    let index = chunk.add_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+    chunk.write0(OP_CLOSURE);
+    chunk.write0(index as u8);
+    chunk.write0(1);
+    chunk.write0(0);
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    chunk.write0(OP_RETURN);
+    assert_eq!(run(&chunk), Ok(Value::Number(5.0)));
+}
+
+/// Sets a captured local through its open upvalue and reads the local back
+#[test]
+fn set_open_upvalue() {
+    let mut body = Chunk::new();
+    body.write_f64_0(7.0);
+    body.write0(OP_SET_UPVALUE);
+    body.write0(0);
+    body.write0(OP_RETURN);
+    let function = Function { name: "inner".to_string(), arity: 0, upvalue_count: 1, chunk: body };
+
+    let mut chunk = Chunk::new();
+    chunk.write_f64_0(5.0);
+    let index = chunk.add_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+    chunk.write0(OP_CLOSURE);
+    chunk.write0(index as u8);
+    chunk.write0(1);
+    chunk.write0(0);
+    chunk.write0(OP_CALL);
+    chunk.write0(0);
+    chunk.write0(OP_POP);
+    chunk.write0(OP_GET_LOCAL);
+    chunk.write0(0);
+    chunk.write0(OP_RETURN);
+    assert_eq!(run(&chunk), Ok(Value::Number(7.0)));
+}
@@ -0,0 +1,201 @@
+//! Runs generated and corrupted bytecode to check that the VM reports broken code as an error instead of panicking.
+//!
+//! Every run is given a limited amount of fuel, so that code looping forever ends with an error as well.
+
+use crate::bytecode::chunk::{Chunk, MAX_FUNCTION_DEPTH};
+use crate::bytecode::codes::*;
+use crate::bytecode::disassembler::disassemble;
+use crate::vm::error::ErrorKind;
+use crate::vm::value::{Function, Obj, Value};
+use crate::vm::Vm;
+use std::ops::Range;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+const ITERATIONS: usize = 3000;
+const FUEL: u64 = 10_000;
+
+/// A xorshift generator, so that failures are reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Mostly opcodes, with small operands mixed in
+    fn byte(&mut self) -> u8 {
+        if self.below(3) == 0 { self.below(4) as u8 } else { self.below(0x2B) as u8 }
+    }
+}
+
+fn random_chunk(rng: &mut Rng, depth: usize) -> Chunk {
+    let mut chunk = Chunk::new();
+    for _ in 0..rng.below(48) {
+        chunk.write0(rng.byte());
+    }
+
+    chunk.push_constant(Value::from("x")).unwrap();
+    chunk.push_constant(Value::Number(rng.below(4) as f64)).unwrap();
+    if depth < 2 {
+        let function = Function {
+            name: format!("f{}", depth),
+            arity: rng.below(3) as u8,
+            upvalue_count: rng.below(3) as u8,
+            chunk: random_chunk(rng, depth + 1),
+        };
+        chunk.push_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+    }
+    chunk
+}
+
+/// Runs the chunk both with and without the verifier, failing with its disassembly if either panics
+fn assert_no_panic(chunk: &Chunk) {
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut vm = Vm::new();
+        vm.set_fuel(Some(FUEL));
+        let _ = vm.run(chunk);
+
+        let mut vm = Vm::new();
+        vm.set_fuel(Some(FUEL));
+        let _ = vm.run_unverified(chunk);
+    }));
+
+    if result.is_err() {
+        panic!("Running this chunk panicked:\n{}", disassemble(chunk, "script"));
+    }
+}
+
+/// The ranges of a serialized chunk that hold code, including the code of nested functions
+fn code_ranges(bytes: &[u8]) -> Vec<Range<usize>> {
+    fn read_u32(bytes: &[u8], position: &mut usize) -> usize {
+        let value = u32::from_be_bytes(bytes[*position..*position + 4].try_into().unwrap());
+        *position += 4;
+        value as usize
+    }
+
+    fn read_chunk(bytes: &[u8], position: &mut usize, ranges: &mut Vec<Range<usize>>) {
+        let code_len = read_u32(bytes, position);
+        ranges.push(*position..*position + code_len);
+        *position += code_len;
+        *position += read_u32(bytes, position) * 12;
+
+        for _ in 0..read_u32(bytes, position) {
+            *position += 1;
+            match bytes[*position - 1] {
+                0x00 => *position += 8,
+                0x01 => *position += read_u32(bytes, position),
+                0x02 => {
+                    *position += read_u32(bytes, position) + 2;
+                    read_chunk(bytes, position, ranges);
+                }
+                0x03 => *position += 1,
+                _ => {}
+            }
+        }
+    }
+
+    // Skips the magic bytes and format version
+    let mut position = 6;
+    let mut ranges = Vec::new();
+    read_chunk(bytes, &mut position, &mut ranges);
+    ranges.retain(|range| !range.is_empty());
+    ranges
+}
+
+/// Source for functions nested `depth` deep, each returning the one nested in it
+fn nested_functions(depth: usize) -> String {
+    let mut source = "return x;".to_string();
+    for level in (0..depth).rev() {
+        source = format!("fun f{}() {{ let x = {}; {} }}", level, level, source);
+        if level > 0 {
+            source.push_str(&format!(" return f{};", level));
+        }
+    }
+    source + " f0();"
+}
+
+#[test]
+fn broken_code_is_an_internal_error() {
+    let programs: [&[u8]; 5] = [
+        &[OP_POP],
+        &[OP_ADD],
+        &[OP_GET_LOCAL, 3],
+        &[OP_CONTANT, 9],
+        &[OP_NIL, OP_LOOP, 0, 9],
+    ];
+
+    for code in programs {
+        let mut chunk = Chunk::new();
+        code.iter().for_each(|byte| chunk.write0(*byte));
+        let error = Vm::new().run_unverified(&chunk).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Internal, "{:?} gave {}", code, error);
+    }
+}
+
+#[test]
+fn random_bytecode() {
+    let mut rng = Rng(0x5eed_f095);
+    for _ in 0..ITERATIONS {
+        assert_no_panic(&random_chunk(&mut rng, 0));
+    }
+}
+
+#[test]
+fn corrupted_programs() {
+    let sources = [
+        "let a = 1; let b = a + 2; b * 3",
+        "fun add(a, b) { return a + b; } add(1, 2)",
+        "fun outer() { let x = 1; fun inner() { x = x + 1; return x; } return inner; } outer()()",
+        "{ let a = \"fops\"; let b = a + \"!\"; to_string(b); }",
+        "if (1 < 2) { 3; } else { 4; } let c = true && false || !true;",
+        "let i = 0; while (i < 5) { i = i + 1; } repeat (3) { i = i * 2; } i",
+        &nested_functions(40),
+    ];
+    let programs: Vec<(Vec<u8>, Vec<Range<usize>>)> = sources.iter()
+        .map(|source| crate::compiler::compile(source, true).unwrap().serialize().unwrap())
+        .map(|bytes| {
+            let ranges = code_ranges(&bytes);
+            (bytes, ranges)
+        })
+        .collect();
+
+    // Only code is corrupted, as the deserializer already rejects most broken headers and constants
+    let mut rng = Rng(0xc0ff_ee15);
+    for _ in 0..ITERATIONS {
+        let (bytes, ranges) = &programs[rng.below(programs.len())];
+        let mut bytes = bytes.clone();
+        for _ in 0..=rng.below(3) {
+            let range = &ranges[rng.below(ranges.len())];
+            let index = range.start + rng.below(range.len());
+            bytes[index] = if rng.below(2) == 0 { rng.byte() } else { rng.next() as u8 };
+        }
+
+        let chunk = catch_unwind(|| Chunk::deserialize(&bytes)).expect("Deserializing panicked");
+        if let Ok(chunk) = chunk {
+            assert_no_panic(&chunk);
+        }
+    }
+}
+
+#[test]
+fn deeply_nested_functions() {
+    let chunk = crate::compiler::compile(&nested_functions(MAX_FUNCTION_DEPTH), true).unwrap();
+    let bytes = chunk.serialize().unwrap();
+    assert_no_panic(&Chunk::deserialize(&bytes).unwrap());
+
+    // The compiler refuses to nest any deeper, so the script is wrapped in one more function by hand
+    let mut wrapper = Chunk::new();
+    let function = Function { name: "wrapper".to_string(), arity: 0, upvalue_count: 0, chunk };
+    wrapper.push_constant(Value::Obj(Obj::Function(Rc::new(function)))).unwrap();
+    let bytes = wrapper.serialize().unwrap();
+    let error = catch_unwind(|| Chunk::deserialize(&bytes)).expect("Deserializing panicked").unwrap_err();
+    assert!(error.starts_with("Functions nested more than 256 deep"), "{}", error);
+}
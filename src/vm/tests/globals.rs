@@ -0,0 +1,20 @@
+use fops_macros::vm_test;
+use crate::bytecode::codes::*;
+
+// The leading "x", OP_POP puts the name "x" at constant index 0
+
+#[test]
+fn define_and_get_global() {
+    vm_test!("x", OP_POP, 5.0, OP_DEFINE_GLOBAL, 0, OP_GET_GLOBAL, 0 => 5.0);
+}
+
+#[test]
+fn set_global() {
+    vm_test!("x", OP_POP, 5.0, OP_DEFINE_GLOBAL, 0, 6.0, OP_SET_GLOBAL, 0, OP_POP, OP_GET_GLOBAL, 0 => 6.0);
+}
+
+#[test]
+fn undefined_global() {
+    vm_test!("x", OP_POP, OP_GET_GLOBAL, 0 => !);
+    vm_test!("x", OP_POP, 5.0, OP_SET_GLOBAL, 0 => !);
+}
@@ -0,0 +1,22 @@
+use fops_macros::vm_test;
+use crate::bytecode::codes::*;
+use crate::vm::value::NIL;
+
+#[test]
+fn jump() {
+    vm_test!(OP_TRUE, OP_JUMP, 0, 1, OP_NOT => true);
+    vm_test!(OP_TRUE, OP_JUMP, 0, 0, OP_NOT => false);
+}
+
+#[test]
+fn jump_if_false() {
+    vm_test!(OP_FALSE, OP_JUMP_IF_FALSE, 0, 2, OP_POP, OP_NIL => false);
+    vm_test!(OP_TRUE, OP_JUMP_IF_FALSE, 0, 2, OP_POP, OP_NIL => NIL);
+}
+
+#[test]
+fn jump_if_false_requires_bool() {
+    vm_test!(OP_NIL, OP_JUMP_IF_FALSE, 0, 0 => !);
+    vm_test!(0.0, OP_JUMP_IF_FALSE, 0, 0 => !);
+    vm_test!("", OP_JUMP_IF_FALSE, 0, 0 => !);
+}
@@ -0,0 +1,15 @@
+use fops_macros::vm_test;
+use crate::bytecode::codes::*;
+
+#[test]
+fn get_local() {
+    vm_test!(5.0, OP_GET_LOCAL, 0 => 5.0);
+    vm_test!(5.0, 6.0, OP_GET_LOCAL, 0 => 5.0);
+    vm_test!(5.0, 6.0, OP_GET_LOCAL, 1 => 6.0);
+}
+
+#[test]
+fn set_local() {
+    vm_test!(5.0, 6.0, OP_SET_LOCAL, 0 => 6.0);
+    vm_test!(5.0, 6.0, OP_SET_LOCAL, 0, OP_POP, OP_GET_LOCAL, 0 => 6.0);
+}
@@ -0,0 +1,42 @@
+use fops_macros::vm_test;
+use crate::bytecode::codes::*;
+
+#[test]
+fn countdown_loop() {
+    // let i = 3; while (i > 0) i = i - 1; i
+    vm_test!(
+        3.0,
+        OP_GET_LOCAL, 0, 0.0, OP_GREATER_THAN,
+        OP_JUMP_IF_FALSE, 0, 19,
+        OP_POP,
+        OP_GET_LOCAL, 0, 1.0, OP_SUBTRACT, OP_SET_LOCAL, 0, OP_POP,
+        OP_LOOP, 0, 34,
+        OP_POP,
+        OP_GET_LOCAL, 0
+        => 0.0
+    );
+}
+
+#[test]
+fn loop_with_non_bool_condition() {
+    vm_test!(OP_NIL, OP_JUMP_IF_FALSE, 0, 4, OP_POP, OP_LOOP, 0, 7 => !);
+}
+
+#[test]
+fn counted_repeat() {
+    // let total = 0; repeat (3) total = total + 2; total
+    vm_test!(
+        0.0,
+        3.0,
+        OP_GET_LOCAL, 1, 0.0, OP_GREATER_THAN,
+        OP_JUMP_IF_FALSE, 0, 34,
+        OP_POP,
+        OP_GET_LOCAL, 1, 1.0, OP_SUBTRACT, OP_SET_LOCAL, 1, OP_POP,
+        OP_GET_LOCAL, 0, 2.0, OP_ADD, OP_SET_LOCAL, 0, OP_POP,
+        OP_LOOP, 0, 49,
+        OP_POP,
+        OP_POP,
+        OP_GET_LOCAL, 0
+        => 6.0
+    );
+}
@@ -14,4 +14,6 @@ fn illegal_operations() {
     vm_test!(15.0, OP_NIL, OP_MULTIPLY => !);
     vm_test!(15.0, OP_NIL, OP_DIVIDE => !);
     vm_test!(15.0, OP_NIL, OP_NOT => !);
+    vm_test!(OP_NIL, OP_NIL, OP_LESS_THAN => !);
+    vm_test!(OP_NIL, OP_NIL, OP_GREATER_THAN_OR_EQUALS => !);
 }
@@ -40,6 +40,29 @@ fn division() {
     vm_test!(15.0, 0.0, OP_DIVIDE => Value::Number(f64::INFINITY));
 }
 
+#[test]
+fn comparison() {
+    vm_test!(1.0, 2.0, OP_LESS_THAN => true);
+    vm_test!(2.0, 2.0, OP_LESS_THAN => false);
+    vm_test!(2.0, 2.0, OP_LESS_THAN_OR_EQUALS => true);
+    vm_test!(3.0, 2.0, OP_LESS_THAN_OR_EQUALS => false);
+    vm_test!(3.0, 2.0, OP_GREATER_THAN => true);
+    vm_test!(2.0, 2.0, OP_GREATER_THAN => false);
+    vm_test!(2.0, 2.0, OP_GREATER_THAN_OR_EQUALS => true);
+    vm_test!(1.0, 2.0, OP_GREATER_THAN_OR_EQUALS => false);
+    let nan = f64::NAN;
+    vm_test!(nan, 1.0, OP_LESS_THAN => false);
+    vm_test!(nan, 1.0, OP_GREATER_THAN_OR_EQUALS => false);
+}
+
+#[test]
+fn illegal_comparison() {
+    vm_test!(1.0, "1", OP_LESS_THAN => !);
+    vm_test!("1", 1.0, OP_GREATER_THAN => !);
+    vm_test!(1.0, OP_NIL, OP_LESS_THAN_OR_EQUALS => !);
+    vm_test!(1.0, OP_TRUE, OP_GREATER_THAN_OR_EQUALS => !);
+}
+
 #[test]
 fn multiplication() {
     vm_test!(15.0, 5.0, OP_MULTIPLY => 75.0);
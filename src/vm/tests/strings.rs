@@ -15,6 +15,17 @@ fn comparison() {
     vm_test!("One", "Two", OP_NOT_EQUALS => true);
 }
 
+#[test]
+fn ordering() {
+    vm_test!("apple", "banana", OP_LESS_THAN => true);
+    vm_test!("banana", "apple", OP_LESS_THAN => false);
+    vm_test!("apple", "apple", OP_LESS_THAN_OR_EQUALS => true);
+    vm_test!("apple", "apples", OP_GREATER_THAN => false);
+    vm_test!("b", "abc", OP_GREATER_THAN => true);
+    vm_test!("abc", "abc", OP_GREATER_THAN_OR_EQUALS => true);
+    vm_test!("Z", "a", OP_LESS_THAN => true);
+}
+
 #[test]
 fn concatenation() {
     vm_test!("Hello, ", "world!", OP_ADD => "Hello, world!");
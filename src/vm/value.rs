@@ -1,4 +1,10 @@
-use std::fmt::{Display, Formatter};
+use crate::bytecode::chunk::Chunk;
+use crate::vm::error::RuntimeError;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::ptr;
+use std::rc::Rc;
 
 pub const NIL: Value = Value::Nil;
 pub const TRUE: Value = Value::Bool(true);
@@ -14,14 +20,90 @@ pub enum Value {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Obj {
-    StringObj { value: String }
+    StringObj { value: String },
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+    Closure(Rc<Closure>),
+    /// Lists are handed to scripts by natives, and compare equal when their elements are equal
+    List(Rc<Vec<Value>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: u8,
+    pub upvalue_count: u8,
+    pub chunk: Chunk,
+}
+
+/// Functions are only equal to themselves
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// A function together with the variables it captured from the functions enclosing it
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// Closures are only equal to themselves
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// A captured variable, which lives on the stack until the scope declaring it ends
+#[derive(Debug)]
+pub enum Upvalue {
+    /// Index of the stack slot holding the variable
+    Open(usize),
+    Closed(Value),
+}
+
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+/// The body of a native, which is handed the VM that called it
+pub type NativeBody = Box<dyn Fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError>>;
+
+/// A function implemented in Rust
+pub struct Native {
+    pub name: String,
+    pub arity: u8,
+    /// Only ever called with `arity` arguments
+    pub function: NativeBody,
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Native").field("name", &self.name).field("arity", &self.arity).finish_non_exhaustive()
+    }
+}
+
+/// Natives are only equal to themselves
+impl PartialEq for Native {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
 }
 
 impl Value {
     pub fn is_string(&self) -> bool {
-        match self { 
-            Value::Obj(Obj::StringObj { .. }) => true,
-            _ => false
+        matches!(self, Value::Obj(Obj::StringObj { .. }))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::Nil => "nil",
+            Value::Obj(Obj::StringObj { .. }) => "string",
+            Value::Obj(Obj::Function(_)) | Value::Obj(Obj::Closure(_)) => "function",
+            Value::Obj(Obj::Native(_)) => "native",
+            Value::Obj(Obj::List(_)) => "list",
         }
     }
 }
@@ -41,10 +123,29 @@ impl Display for Obj {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Obj::StringObj { value } => write!(f, "{}", value),
+            Obj::Function(function) => write!(f, "{}", function),
+            Obj::Native(native) => write!(f, "<native {}>", native.name),
+            Obj::Closure(closure) => write!(f, "{}", closure.function),
+            Obj::List(elements) => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
 
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fun {}>", self.name)
+    }
+}
+
 impl From<String> for Value {
     fn from(string: String) -> Value {
         Value::Obj(Obj::StringObj { value: string })